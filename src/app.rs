@@ -1,75 +1,13 @@
-use std::{borrow::Cow, collections::HashMap};
+use std::{borrow::Cow, sync::Arc};
 
-use rodio::{OutputStreamHandle, OutputStream, Sink};
+use rodio::{OutputStream, OutputStreamBuilder, Sink};
 use eframe::egui::{self, DragValue, TextStyle};
 use egui_node_graph2::*;
 use crate::fm;
-
-
-// ========= First, define your user data types =============
-
-// Wave struct describing sine waves and stuff
-
-
-/// The NodeData holds a custom data struct inside each node. It's useful to
-/// store additional information that doesn't live in parameters. For this
-/// example, the node data stores the template (i.e. the "type") of the node.
-#[cfg_attr(feature = "persistence", derive(serde::Serialize, serde::Deserialize))]
-pub struct MyNodeData {
-    template: fm::Stream,
-}
-
-/// `DataType`s are what defines the possible range of connections when
-/// attaching two ports together. The graph UI will make sure to not allow
-/// attaching incompatible datatypes.
-#[derive(PartialEq, Eq)]
-#[cfg_attr(feature = "persistence", derive(serde::Serialize, serde::Deserialize))]
-pub enum MyDataType {
-    Stream,
-    Const,
-}
-
-/// In the graph, input parameters can optionally have a constant value. This
-/// value can be directly edited in a widget inside the node itself.
-///
-/// There will usually be a correspondence between DataTypes and ValueTypes. But
-/// this library makes no attempt to check this consistency. For instance, it is
-/// up to the user code in this example to make sure no parameter is created
-/// with a DataType of Scalar and a ValueType of Vec2.
-#[derive(Clone, Debug)]
-#[cfg_attr(feature = "persistence", derive(serde::Serialize, serde::Deserialize))]
-pub enum MyValueType {
-    Stream { value: fm::Stream },
-    Const  { value: f32 }
-}
-
-impl Default for MyValueType {
-    fn default() -> Self {
-        // NOTE: This is just a dummy `Default` implementation. The library
-        // requires it to circumvent some internal borrow checker issues.
-        Self::Stream { value: fm::Stream::Empty(fm::Empty::new()) }
-    }
-}
-
-impl MyValueType {
-    /// Tries to downcast this value type to a vector
-    pub fn try_to_stream(self) -> anyhow::Result<fm::Stream> {
-        if let MyValueType::Stream { value } = self {
-            Ok(value)
-        } else {
-            anyhow::bail!("Invalid cast from {:?} to vec2", self)
-        }
-    }
-
-    /// Tries to downcast this value type to a scalar
-    pub fn try_to_const(self) -> anyhow::Result<f32> {
-        if let MyValueType::Const { value } = self {
-            Ok(value)
-        } else {
-            anyhow::bail!("Invalid cast from {:?} to scalar", self)
-        }
-    }
-}
+use crate::graph::*;
+use crate::node::{Port, Registry};
+use crate::patch::{read_patch, write_patch};
+use crate::runtime::{Program, Remote, Runtime};
 
 /// The response type is used to encode side-effects produced when drawing a
 /// node in the graph. Most side-effects (creating new nodes, deleting existing
@@ -79,15 +17,12 @@ impl MyValueType {
 pub enum MyResponse {
     SetActiveNode(NodeId),
     ClearActiveNode,
-}
-
-/// The graph 'global' state. This state struct is passed around to the node and
-/// parameter drawing callbacks. The contents of this struct are entirely up to
-/// the user. For this example, we use it to keep track of the 'active' node.
-#[derive(Default)]
-#[cfg_attr(feature = "persistence", derive(serde::Serialize, serde::Deserialize))]
-pub struct MyGraphState {
-    pub active_node: Option<NodeId>,
+    ToggleMute(NodeId),
+    ToggleSolo(NodeId),
+    // The value of a Parameter node changed.
+    SetParameter(NodeId),
+    NoteOn(NodeId),
+    NoteOff(NodeId),
 }
 
 // =========== Then, you need to implement some traits ============
@@ -98,6 +33,12 @@ impl DataTypeTrait<MyGraphState> for MyDataType {
         match self {
             MyDataType::Stream => egui::Color32::from_rgb(38, 109, 211),
             MyDataType::Const  => egui::Color32::from_rgb(200, 15, 30),
+            MyDataType::Wavetable => egui::Color32::from_rgb(40, 160, 90),
+            MyDataType::Sample => egui::Color32::from_rgb(220, 150, 40),
+            MyDataType::Choice => egui::Color32::from_rgb(140, 140, 140),
+            MyDataType::Partials => egui::Color32::from_rgb(150, 80, 200),
+            MyDataType::Curve => egui::Color32::from_rgb(200, 90, 140),
+            MyDataType::Text => egui::Color32::from_rgb(220, 220, 220),
         }
     }
 
@@ -105,374 +46,16 @@ impl DataTypeTrait<MyGraphState> for MyDataType {
         match self {
             MyDataType::Stream => Cow::Borrowed("Stream"),
             MyDataType::Const  => Cow::Borrowed("Constant"),
+            MyDataType::Wavetable => Cow::Borrowed("Wavetable"),
+            MyDataType::Sample => Cow::Borrowed("Sample"),
+            MyDataType::Choice => Cow::Borrowed("Choice"),
+            MyDataType::Partials => Cow::Borrowed("Partials"),
+            MyDataType::Curve => Cow::Borrowed("Curve"),
+            MyDataType::Text => Cow::Borrowed("Text"),
         }
     }
 }
 
-// A trait for the node kinds, which tells the library how to build new nodes
-// from the templates in the node finder
-impl NodeTemplateTrait for fm::Stream {
-    type NodeData = MyNodeData;
-    type DataType = MyDataType;
-    type ValueType = MyValueType;
-    type UserState = MyGraphState;
-    type CategoryType = &'static str;
-
-    fn node_finder_label(&self, _user_state: &mut Self::UserState) -> Cow<'_, str> {
-        Cow::Borrowed(match self {
-            Self::SineWave(_) => "Sine Wave",
-            Self::SquareWave(_) => "Square Wave",
-            Self::TriangleWave(_) => "Triangle Wave",
-            Self::SawtoothWave(_) => "Sawtooth",
-            Self::ModulatedSineWave(_) => "Modulator",
-            Self::Mix(_) => "Mix",
-            Self::Empty(_) => "Empty",
-            Self::Envelope(_) => "Envelope",
-            Self::Perlin (_) => "Perlin Noise",
-            Self::WhiteNoise (_) => "White Noise",
-            Self::Add (_) => "Add",
-            Self::Multiply (_) => "Multiply",
-            Self::Const(_) => "Const",
-        })
-    }
-
-    // this is what allows the library to show collapsible lists in the node finder.
-    fn node_finder_categories(&self, _user_state: &mut Self::UserState) -> Vec<&'static str> {
-        match self {
-            Self::SineWave(_) => vec!["Waves"],
-            Self::SquareWave(_) => vec!["Waves"],
-            Self::TriangleWave(_) => vec!["Waves"],
-            Self::SawtoothWave(_) => vec!["Waves"],
-            Self::ModulatedSineWave(_) => vec![],
-            Self::Mix(_) => vec![],
-            Self::Empty(_) => vec![],
-            Self::Envelope(_) => vec![],
-            Self::Perlin (_) => vec!["Noise"],
-            Self::WhiteNoise (_) => vec!["Noise"],
-            Self::Add(_) => vec!["Math"],
-            Self::Multiply(_) => vec!["Math"],
-            Self::Const(_) => vec!["Math"],
-        }
-    }
-
-    fn node_graph_label(&self, user_state: &mut Self::UserState) -> String {
-        // It's okay to delegate this to node_finder_label if you don't want to
-        // show different names in the node finder and the node itself.
-        self.node_finder_label(user_state).into()
-    }
-
-    fn user_data(&self, _user_state: &mut Self::UserState) -> Self::NodeData {
-        MyNodeData { template: self.clone() }
-    }
-
-    fn build_node(
-        &self,
-        graph: &mut Graph<Self::NodeData, Self::DataType, Self::ValueType>,
-        _user_state: &mut Self::UserState,
-        node_id: NodeId,
-    ) {
-        // The nodes are created empty by default. This function needs to take
-        // care of creating the desired inputs and outputs based on the template
-        let add_const_param = |graph: &mut Graph<MyNodeData, MyDataType, MyValueType>, name: &str, value: f32| {
-            graph.add_input_param(
-                node_id,
-                name.into(),
-                MyDataType::Const,
-                MyValueType::Const { value },
-                InputParamKind::ConnectionOrConstant,
-                true,
-            );
-        };
-        match self {
-            Self::SineWave(_) => {
-                // The first input param doesn't use the closure so we can comment
-                // it in more detail.
-                graph.add_input_param(
-                    node_id,
-                    // This is the name of the parameter. Can be later used to
-                    // retrieve the value. Parameter names should be unique.
-                    "Frequency".into(),
-                    // The data type for this input. In this case, a scalar
-                    MyDataType::Const,
-                    // The value type for this input. We store zero as default
-                    MyValueType::Const { value: 440.0 }, 
-                    // The input parameter kind. This allows defining whether a
-                    // parameter accepts input connections and/or an inline
-                    // widget to set its value.
-                    InputParamKind::ConnectionOrConstant,
-                    true,
-                );
-
-                graph.add_input_param(
-                    node_id,
-                    "Phase Shift".into(),
-                    MyDataType::Const,
-                    MyValueType::Const { value: 0.0 },
-                    InputParamKind::ConnectionOrConstant,
-                    true,
-                );
-
-                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
-            }
-            Self::SquareWave(_) => {
-                // The first input param doesn't use the closure so we can comment
-                // it in more detail.
-                graph.add_input_param(
-                    node_id,
-                    // This is the name of the parameter. Can be later used to
-                    // retrieve the value. Parameter names should be unique.
-                    "Frequency".into(),
-                    // The data type for this input. In this case, a scalar
-                    MyDataType::Const,
-                    // The value type for this input. We store zero as default
-                    MyValueType::Const { value: 440.0 }, 
-                    // The input parameter kind. This allows defining whether a
-                    // parameter accepts input connections and/or an inline
-                    // widget to set its value.
-                    InputParamKind::ConnectionOrConstant,
-                    true,
-                );
-
-                graph.add_input_param(
-                    node_id,
-                    "Phase Shift".into(),
-                    MyDataType::Const,
-                    MyValueType::Const { value: 0.0 },
-                    InputParamKind::ConnectionOrConstant,
-                    true,
-                );
-
-                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
-            }
-            Self::TriangleWave(_) => {
-                // The first input param doesn't use the closure so we can comment
-                // it in more detail.
-                graph.add_input_param(
-                    node_id,
-                    // This is the name of the parameter. Can be later used to
-                    // retrieve the value. Parameter names should be unique.
-                    "Frequency".into(),
-                    // The data type for this input. In this case, a scalar
-                    MyDataType::Const,
-                    // The value type for this input. We store zero as default
-                    MyValueType::Const { value: 440.0 }, 
-                    // The input parameter kind. This allows defining whether a
-                    // parameter accepts input connections and/or an inline
-                    // widget to set its value.
-                    InputParamKind::ConnectionOrConstant,
-                    true,
-                );
-
-                graph.add_input_param(
-                    node_id,
-                    "Phase Shift".into(),
-                    MyDataType::Const,
-                    MyValueType::Const { value: 0.0 },
-                    InputParamKind::ConnectionOrConstant,
-                    true,
-                );
-
-                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
-            }
-            Self::SawtoothWave(_) => {
-                // The first input param doesn't use the closure so we can comment
-                // it in more detail.
-                graph.add_input_param(
-                    node_id,
-                    // This is the name of the parameter. Can be later used to
-                    // retrieve the value. Parameter names should be unique.
-                    "Frequency".into(),
-                    // The data type for this input. In this case, a scalar
-                    MyDataType::Const,
-                    // The value type for this input. We store zero as default
-                    MyValueType::Const { value: 440.0 }, 
-                    // The input parameter kind. This allows defining whether a
-                    // parameter accepts input connections and/or an inline
-                    // widget to set its value.
-                    InputParamKind::ConnectionOrConstant,
-                    true,
-                );
-
-                graph.add_input_param(
-                    node_id,
-                    "Phase Shift".into(),
-                    MyDataType::Const,
-                    MyValueType::Const { value: 0.0 },
-                    InputParamKind::ConnectionOrConstant,
-                    true,
-                );
-
-                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
-            }
-            Self::ModulatedSineWave(_) => {
-                graph.add_input_param(
-                    node_id,
-                    "Frequency".into(),
-                    MyDataType::Const,
-                    MyValueType::Const { value: 0.0 },
-                    InputParamKind::ConnectionOrConstant,
-                    true,
-                );
-
-                graph.add_input_param(
-                    node_id,
-                    "Modulation".into(),
-                    MyDataType::Stream,
-                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
-                    InputParamKind::ConnectionOnly,
-                    true,
-                );
-
-                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
-            }
-            Self::Mix(_) => {
-                graph.add_input_param(
-                    node_id,
-                    "p".into(),
-                    MyDataType::Const,
-                    MyValueType::Const { value: 0.5 },
-                    InputParamKind::ConnectionOrConstant,
-                    true,
-                );   
-
-                graph.add_input_param(
-                    node_id,
-                    "A".into(),
-                    MyDataType::Stream,
-                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
-                    InputParamKind::ConnectionOnly,
-                    true,
-                );                 
-                
-                graph.add_input_param(
-                    node_id,
-                    "B".into(),
-                    MyDataType::Stream,
-                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
-                    InputParamKind::ConnectionOnly,
-                    true,
-                );
-
-                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
-            }
-            Self::Const(_) => {
-                graph.add_input_param(
-                    node_id,
-                    "Value".into(),
-                    MyDataType::Const,
-                    MyValueType::Const { value: 0.5 },
-                    InputParamKind::ConnectionOrConstant,
-                    true,
-                );
-
-                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream); 
-            },
-            Self::Empty(_) => { graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream); },
-            Self::Envelope(_) => {
-                graph.add_input_param(
-                    node_id,
-                    "Stream".into(),
-                    MyDataType::Stream,
-                    MyValueType::Stream { value: fm::Stream::default() }, 
-                    InputParamKind::ConnectionOrConstant,
-                    true,
-                );
-
-                add_const_param(graph, "Attack Duration", 0.3);
-                add_const_param(graph, "Attack Amplitude", 1.0);
-                add_const_param(graph, "Decay Duration", 0.3);
-                add_const_param(graph, "Sustain Duration", 2.0);
-                add_const_param(graph, "Sustain Amplitude", 0.6);
-                add_const_param(graph, "Release Duration", 1.0);
-
-                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
-            }
-            Self::WhiteNoise(_) => {
-                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
-            }
-            Self::Perlin(_) => {
-                graph.add_input_param(
-                    node_id,
-                    "Scale".into(),
-                    MyDataType::Const,
-                    MyValueType::Const { value: 1.0 }, 
-                    InputParamKind::ConnectionOrConstant,
-                    true,
-                );
-                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
-            }
-            Self::Add(_) => {
-                graph.add_input_param(
-                    node_id,
-                    "A".into(),
-                    MyDataType::Stream,
-                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
-                    InputParamKind::ConnectionOnly,
-                    true,
-                );                 
-                
-                graph.add_input_param(
-                    node_id,
-                    "B".into(),
-                    MyDataType::Stream,
-                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
-                    InputParamKind::ConnectionOnly,
-                    true,
-                );
-
-                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
-            }
-            Self::Multiply(_) => {
-                graph.add_input_param(
-                    node_id,
-                    "A".into(),
-                    MyDataType::Stream,
-                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
-                    InputParamKind::ConnectionOnly,
-                    true,
-                );                 
-                
-                graph.add_input_param(
-                    node_id,
-                    "B".into(),
-                    MyDataType::Stream,
-                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
-                    InputParamKind::ConnectionOnly,
-                    true,
-                );
-
-                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
-            }
-        }
-    }
-}
-
-pub struct AllMyNodeTemplates;
-impl NodeTemplateIter for AllMyNodeTemplates {
-    type Item = fm::Stream;
-
-    fn all_kinds(&self) -> Vec<Self::Item> {
-        // This function must return a list of node kinds, which the node finder
-        // will use to display it to the user. Crates like strum can reduce the
-        // boilerplate in enumerating all variants of an enum.
-        vec![
-            fm::Stream::SineWave(fm::SineWave::new()),
-            fm::Stream::SquareWave(fm::SquareWave::new()),
-            fm::Stream::TriangleWave(fm::TriangleWave::new()),
-            fm::Stream::SawtoothWave(fm::SawtoothWave::new()),
-            fm::Stream::ModulatedSineWave(fm::ModulatedSineWave::new()),
-            fm::Stream::Mix(fm::Mix::new()),
-            fm::Stream::Const(fm::Const::new()),
-            fm::Stream::Empty(fm::Empty::new()),
-            fm::Stream::Envelope(fm::Envelope::new()),
-            fm::Stream::Perlin(fm::Perlin::new()),
-            fm::Stream::WhiteNoise(fm::WhiteNoise::new()),
-            fm::Stream::Add(fm::Add::new()),
-            fm::Stream::Multiply(fm::Multiply::new()),
-        ]
-    }
-}
-
 impl WidgetValueTrait for MyValueType {
     type Response = MyResponse;
     type UserState = MyGraphState;
@@ -480,27 +63,174 @@ impl WidgetValueTrait for MyValueType {
     fn value_widget(
         &mut self,
         param_name: &str,
-        _node_id: NodeId,
+        node_id: NodeId,
         ui: &mut egui::Ui,
         _user_state: &mut MyGraphState,
-        _node_data: &MyNodeData,
+        node_data: &MyNodeData,
     ) -> Vec<MyResponse> {
         // This trait is used to tell the library which UI to display for the
         // inline parameter widgets.
+        let mut responses = Vec::new();
         match self {
+            MyValueType::Stream { value: fm::Stream::Const(constant) } => {
+                let mut value = constant.val();
+                ui.horizontal(|ui| {
+                    ui.label(param_name);
+                    ui.add(DragValue::new(&mut value));
+                });
+                constant.set_val(value);
+            }
             MyValueType::Stream { value: _ } => { }
             MyValueType::Const { value }  => { 
+                // Registered nodes declare the range of their params
+                let range = match &node_data.template {
+                    fm::Stream::Custom(node) => node.node().ports().into_iter().find_map(|port| match port {
+                        Port::Param { name, range, .. } if name == param_name => Some(range),
+                        _ => None,
+                    }),
+                    _ => None,
+                };
+                let changed = ui.horizontal(|ui| {
+                    ui.label(param_name);
+                    match range {
+                        Some(range) => ui.add(DragValue::new(value).range(range)),
+                        None => ui.add(DragValue::new(value)),
+                    }.changed()
+                }).inner;
+                // Parameters are set on the playing program, without compiling
+                if changed && matches!(node_data.template, fm::Stream::Parameter(_)) {
+                    responses.push(MyResponse::SetParameter(node_id));
+                }
+            }
+            MyValueType::Wavetable { path, bank } => {
+                if file_widget(ui, param_name, path, "built-in shapes") {
+                    // An empty path goes back to the generated shapes
+                    let loaded = if path.is_empty() {
+                        Ok(fm::WavetableBank::shapes())
+                    } else {
+                        fm::WavetableBank::load(path)
+                    };
+                    match loaded {
+                        Ok(loaded) => *bank = Arc::new(loaded),
+                        Err(err) => println!("failed to load wavetable {}: {}", path, err),
+                    }
+                }
+            }
+            MyValueType::Sample { path, sample } => {
+                if file_widget(ui, param_name, path, "path to a wav file") {
+                    let loaded = if path.is_empty() {
+                        Ok(fm::SampleBuffer::default())
+                    } else {
+                        fm::SampleBuffer::load(path)
+                    };
+                    match loaded {
+                        Ok(loaded) => *sample = Arc::new(loaded),
+                        Err(err) => println!("failed to load sample {}: {}", path, err),
+                    }
+                }
+            }
+            MyValueType::Partials { value } => {
+                ui.label(format!("{} (ratio, amplitude, detune)", param_name));
+                let mut removed = None;
+                for (index, partial) in value.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.add(DragValue::new(&mut partial.ratio).speed(0.01));
+                        ui.add(DragValue::new(&mut partial.amplitude).speed(0.01));
+                        ui.add(DragValue::new(&mut partial.detune).suffix(" ct"));
+                        if ui.small_button("✖").clicked() {
+                            removed = Some(index);
+                        }
+                    });
+                }
+                if let Some(index) = removed {
+                    value.remove(index);
+                }
+                if ui.small_button("+ Partial").clicked() {
+                    let ratio = value.last().map_or(1.0, |p| p.ratio.floor() + 1.0);
+                    value.push(fm::Partial::new(ratio, 1.0 / ratio));
+                }
+            }
+            MyValueType::Curve { value } => {
+                ui.horizontal(|ui| {
+                    ui.label(param_name);
+                    if ui.small_button("Reset").clicked() {
+                        *value = fm::identity_curve();
+                    }
+                });
+                curve_widget(ui, value);
+            }
+            MyValueType::Text { value } => {
+                ui.horizontal(|ui| {
+                    ui.label(param_name);
+                    ui.add(egui::TextEdit::singleline(value).desired_width(100.0));
+                });
+            }
+            MyValueType::Choice { value, options } => {
                 ui.horizontal(|ui| {
                     ui.label(param_name);
-                    ui.add(DragValue::new(value));
+                    egui::ComboBox::from_id_source((node_id, param_name))
+                        .selected_text(options.get(*value).map(String::as_str).unwrap_or(""))
+                        .show_ui(ui, |ui| {
+                            for (index, option) in options.iter().enumerate() {
+                                ui.selectable_value(value, index, option);
+                            }
+                        });
                 });
             }
         }
         // This allows you to return your responses from the inline widgets.
-        Vec::new()
+        responses
     }
 }
 
+// Draws a path text field with a load button, returns true when the button
+// was clicked.
+fn file_widget(ui: &mut egui::Ui, param_name: &str, path: &mut String, hint: &str) -> bool {
+    ui.label(param_name);
+    ui.horizontal(|ui| {
+        ui.add(egui::TextEdit::singleline(path).hint_text(hint).desired_width(120.0));
+        ui.button("Load").clicked()
+    }).inner
+}
+
+// Draws the points of a curve from -1 to 1 on both axes, clicking or
+// dragging moves the point closest to the pointer.
+fn curve_widget(ui: &mut egui::Ui, points: &mut [f32]) {
+    let (response, painter) = ui.allocate_painter(egui::vec2(160.0, 80.0), egui::Sense::click_and_drag());
+    let rect = response.rect;
+    let last = points.len().saturating_sub(1).max(1) as f32;
+
+    if let Some(pos) = response.interact_pointer_pos() {
+        let x = ((pos.x - rect.left()) / rect.width()).clamp(0.0, 1.0);
+        let y = 1.0 - 2.0 * ((pos.y - rect.top()) / rect.height()).clamp(0.0, 1.0);
+        if let Some(point) = points.get_mut((x * last).round() as usize) {
+            *point = y;
+        }
+    }
+
+    painter.rect_filled(rect, 2.0, egui::Color32::from_gray(30));
+    painter.hline(rect.x_range(), rect.center().y, egui::Stroke::new(1.0, egui::Color32::from_gray(60)));
+    let line = points.iter().enumerate().map(|(i, y)| {
+        egui::pos2(rect.left() + i as f32 / last * rect.width(), rect.center().y - y.clamp(-1.0, 1.0) * rect.height() / 2.0)
+    }).collect();
+    painter.add(egui::Shape::line(line, egui::Stroke::new(1.5, egui::Color32::from_rgb(200, 90, 140))));
+}
+
+// Picks a sample rate from the common ones or any other typed in, returns
+// true when it changed. Dragging only counts once it stops.
+fn rate_widget(ui: &mut egui::Ui, label: &str, sample_rate: &mut u32) -> bool {
+    let before = *sample_rate;
+    egui::ComboBox::from_id_source(label)
+        .selected_text(format!("{} {} Hz", label, sample_rate))
+        .show_ui(ui, |ui| {
+            for rate in [44100, 48000, 96000] {
+                ui.selectable_value(sample_rate, rate, format!("{} Hz", rate));
+            }
+        });
+    let drag = ui.add(DragValue::new(sample_rate).range(8000..=192000).suffix(" Hz"));
+    (*sample_rate != before && !drag.dragged()) || drag.drag_stopped()
+}
+
 impl UserResponseTrait for MyResponse {}
 impl NodeDataTrait for MyNodeData {
     type Response = MyResponse;
@@ -551,11 +281,37 @@ impl NodeDataTrait for MyNodeData {
             }
         }
 
+        if user_state.unreachable.contains(&node_id) {
+            ui.weak("Unreachable");
+        }
+
+        if let fm::Stream::Output(_) = self.template {
+            ui.horizontal(|ui| {
+                if ui.selectable_label(user_state.muted.contains(&node_id), "Mute").clicked() {
+                    responses.push(NodeResponse::User(MyResponse::ToggleMute(node_id)));
+                }
+                if ui.selectable_label(user_state.soloed.contains(&node_id), "Solo").clicked() {
+                    responses.push(NodeResponse::User(MyResponse::ToggleSolo(node_id)));
+                }
+            });
+        }
+
+        // Plays middle C on the playing program for as long as it is held.
+        if let fm::Stream::Note(_) = self.template {
+            let down = ui.button("🎹 Hold to play").is_pointer_button_down_on();
+            if down && user_state.held_note.is_none() {
+                user_state.held_note = Some(node_id);
+                responses.push(NodeResponse::User(MyResponse::NoteOn(node_id)));
+            } else if !down && user_state.held_note == Some(node_id) {
+                user_state.held_note = None;
+                responses.push(NodeResponse::User(MyResponse::NoteOff(node_id)));
+            }
+        }
+
         responses
     }
 }
 
-type MyGraph = Graph<MyNodeData, MyDataType, MyValueType>;
 type MyEditorState =
     GraphEditorState<MyNodeData, MyDataType, MyValueType, fm::Stream, MyGraphState>;
 
@@ -566,22 +322,199 @@ pub struct NodeGraphExample {
 
     user_state: MyGraphState,
 
+    // The node types offered on top of the built-in ones.
+    registry: Registry,
+
     sink: Sink,
+    // Swaps what the sink plays, see `restart`.
+    remote: Remote,
+    volume: f32,
+    playing_outputs: bool,
+    sample_rate: u32,
+    export_path: String,
+    export_seconds: f32,
+    export_sample_rate: u32,
+    export_status: String,
+    // Why the last program or command couldn't be sent, shown under the graph.
+    playback_error: Option<String>,
+    patch_path: String,
     _stream: OutputStream, 
-    _stream_handle: OutputStreamHandle,
 }
 
 impl Default for NodeGraphExample {
     fn default() -> Self {
-        let (_stream, _stream_handle) = OutputStream::try_default().unwrap();
-        let sink = Sink::try_new(&_stream_handle).unwrap();
+        let mut _stream = OutputStreamBuilder::open_default_stream().unwrap();
+        _stream.log_on_drop(false);
+        let sink = Sink::connect_new(_stream.mixer());
+        let volume = 0.8;
+        sink.set_volume(volume);
+        let sample_rate = device_sample_rate().unwrap_or(44100);
+        let mut runtime = Runtime::with_program(Program::silence(sample_rate));
+        let remote = runtime.remote(COMMAND_CAPACITY);
+        sink.append(runtime);
         Self { 
             _stream,
-            _stream_handle,
             sink, 
+            remote,
+            volume,
+            playing_outputs: false,
+            sample_rate,
+            export_path: "export".into(),
+            export_seconds: 5.0,
+            export_sample_rate: sample_rate,
+            export_status: String::new(),
+            playback_error: None,
+            patch_path: "patch.ron".into(),
             state: MyEditorState::default(),
-            user_state: MyGraphState::default()
+            user_state: MyGraphState::default(),
+            registry: Registry::default(),
+        }
+    }
+}
+
+impl NodeGraphExample {
+    /// Creates the editor with the nodes of `registry` in its node finder,
+    /// in place of the default registry.
+    pub fn with_registry(registry: Registry) -> Self {
+        Self { registry, ..Self::default() }
+    }
+}
+
+// The rate the default output device runs at, playing at that rate saves
+// the sink from resampling.
+fn device_sample_rate() -> Option<u32> {
+    use rodio::cpal::traits::{DeviceTrait, HostTrait};
+    let device = rodio::cpal::default_host().default_output_device()?;
+    Some(device.default_output_config().ok()?.sample_rate().0)
+}
+
+// Commands the editor can send between two blocks of audio.
+const COMMAND_CAPACITY: usize = 64;
+
+impl NodeGraphExample {
+    // The sink plays one runtime for as long as the engine runs at the same
+    // rate, playing a node swaps the program the runtime plays. A new rate
+    // needs a new runtime.
+    fn restart(&mut self) {
+        let mut runtime = Runtime::with_program(Program::silence(self.sample_rate));
+        self.remote = runtime.remote(COMMAND_CAPACITY);
+        self.sink.skip_one();
+        self.sink.append(runtime);
+    }
+
+    fn swap(&mut self, program: Program) {
+        self.playback_error = self.remote.swap(program).err().map(|err| format!("Failed to play: {}", err));
+    }
+
+    fn play_active(&mut self, node: NodeId) {
+        self.user_state.active_node = Some(node);
+        self.playing_outputs = false;
+        match plan_for(&self.state.graph, node, self.sample_rate) {
+            Ok(compiled) => {
+                self.user_state.unreachable = compiled.unreachable(&self.state.graph);
+                self.swap(Program::compiled(compiled));
+            }
+            // A feedback loop, shown under the graph until it is fixed.
+            Err(_) => self.user_state.unreachable.clear(),
+        }
+    }
+
+    // Plays the mix of every audible output node, in place of the active node.
+    // The outputs share one plan, so nodes feeding several of them run once.
+    fn play_outputs(&mut self) {
+        let graph = &self.state.graph;
+        let mut compiled = OutputsCache::new(self.sample_rate);
+        let mut failed = None;
+        for node in audible_outputs(graph, &self.user_state) {
+            if let Err(err) = add_output_node(graph, node, &mut compiled) {
+                failed = Some(format!("Failed to evaluate outputs: {}", err));
+                compiled = OutputsCache::new(self.sample_rate);
+                break;
+            }
         }
+        self.user_state.unreachable = compiled.unreachable(graph);
+        self.user_state.active_node = None;
+        self.playing_outputs = true;
+        self.swap(Program::compiled(compiled));
+        if failed.is_some() {
+            self.playback_error = failed;
+        }
+    }
+
+    fn stop(&mut self) {
+        self.playback_error = self.remote.stop().err().map(|err| format!("Failed to stop: {}", err));
+        self.user_state.unreachable.clear();
+    }
+
+    // Sends a Parameter node's value to the playing program, if it reads it.
+    fn set_parameter(&mut self, node: NodeId) -> anyhow::Result<()> {
+        let value = |name| self.state.graph[node].get_input(name).map(|input| self.state.graph[input].value.clone());
+        let name = value("Name")?.try_to_text()?;
+        let value = value("Value")?.try_to_const()?;
+        self.remote.set(&name, value)
+    }
+
+    // Writes each audible output node to its own file in the export folder,
+    // or the active node when the graph has no output nodes. Returns how many
+    // files were written.
+    fn export(&self) -> anyhow::Result<usize> {
+        let graph = &self.state.graph;
+        let has_outputs = graph.nodes.values().any(|node| matches!(node.user_data.template, fm::Stream::Output(_)));
+        let mut files = vec![];
+        if has_outputs {
+            for node in audible_outputs(graph, &self.user_state) {
+                let mut compiled = OutputsCache::new(self.export_sample_rate);
+                let name = match add_output_node(graph, node, &mut compiled)?.trim() {
+                    "" => "output".to_string(),
+                    name => name.replace(['/', '\\'], "_"),
+                };
+                if files.iter().any(|(other, _)| *other == name) {
+                    anyhow::bail!("two outputs are named {}", name);
+                }
+                files.push((name, fm::Master::new(compiled.plan)));
+            }
+        } else if let Some(node) = self.user_state.active_node {
+            let compiled = plan_for(graph, node, self.export_sample_rate)?;
+            files.push((graph[node].label.clone(), fm::Master::new(compiled.plan)));
+        } else {
+            anyhow::bail!("add output nodes or set a node active to export");
+        }
+
+        std::fs::create_dir_all(&self.export_path)?;
+        let count = files.len();
+        let files = files.into_iter().map(|(name, master)| {
+            let path = std::path::Path::new(&self.export_path).join(format!("{}.wav", name));
+            (path.to_string_lossy().into_owned(), master)
+        }).collect();
+        fm::write_wavs(files, self.export_seconds)?;
+        Ok(count)
+    }
+
+    // Saves the graph with the position of each node, in the format
+    // `Patch::open` and the runtime load.
+    fn save_patch(&self) -> anyhow::Result<()> {
+        let positions = &self.state.node_positions;
+        let text = write_patch(&self.state.graph, |node| positions.get(node).map(|pos| [pos.x, pos.y]))?;
+        std::fs::write(&self.patch_path, text)?;
+        Ok(())
+    }
+
+    // Replaces the graph with a saved one. Nodes saved without a position,
+    // like the ones of patches built in code, are laid out in rows.
+    fn open_patch(&mut self) -> anyhow::Result<()> {
+        let (graph, nodes) = read_patch(&std::fs::read_to_string(&self.patch_path)?, &self.registry)?;
+        self.stop();
+        self.playing_outputs = false;
+        self.user_state = MyGraphState::default();
+        let mut state = MyEditorState::default();
+        for (i, (node, position)) in nodes.into_iter().enumerate() {
+            let [x, y] = position.unwrap_or([220.0 * (i % 5) as f32, 180.0 * (i / 5) as f32]);
+            state.node_positions.insert(node, egui::pos2(x, y));
+            state.node_order.push(node);
+        }
+        state.graph = graph;
+        self.state = state;
+        Ok(())
     }
 }
 
@@ -614,16 +547,68 @@ impl eframe::App for NodeGraphExample {
     /// Called each time the UI needs repainting, which may be many times per second.
     /// Put your widgets into a `SidePanel`, `TopPanel`, `CentralPanel`, `Window` or `Area`.
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        // Programs the runtime swapped out are dropped here, off the audio thread.
+        self.remote.collect();
         egui::TopBottomPanel::top("top").show(ctx, |ui| {
             egui::menu::bar(ui, |ui| {
                 egui::widgets::global_dark_light_mode_switch(ui);
+                ui.separator();
+                ui.label("Volume");
+                if ui.add(egui::Slider::new(&mut self.volume, 0.0..=1.0)).changed() {
+                    self.sink.set_volume(self.volume);
+                }
+                ui.separator();
+                // Restarts whatever is playing at the new rate
+                if rate_widget(ui, "Engine", &mut self.sample_rate) {
+                    self.restart();
+                    if self.playing_outputs {
+                        self.play_outputs();
+                    } else if let Some(node) = self.user_state.active_node {
+                        self.play_active(node);
+                    }
+                }
+                ui.separator();
+                let label = if self.playing_outputs { "■ Outputs" } else { "▶ Outputs" };
+                if ui.button(label).clicked() {
+                    if self.playing_outputs {
+                        self.stop();
+                        self.playing_outputs = false;
+                    } else {
+                        self.play_outputs();
+                    }
+                }
+                ui.separator();
+                ui.add(egui::TextEdit::singleline(&mut self.export_path).hint_text("folder").desired_width(160.0));
+                ui.add(DragValue::new(&mut self.export_seconds).range(0.0..=600.0).suffix(" s"));
+                rate_widget(ui, "Export", &mut self.export_sample_rate);
+                if ui.button("Export").clicked() {
+                    self.export_status = match self.export() {
+                        Ok(count) => format!("Exported {} file(s) to {}", count, self.export_path),
+                        Err(err) => format!("Export failed: {}", err),
+                    };
+                }
+                ui.separator();
+                ui.add(egui::TextEdit::singleline(&mut self.patch_path).hint_text("patch file").desired_width(160.0));
+                if ui.button("Save").clicked() {
+                    self.export_status = match self.save_patch() {
+                        Ok(()) => format!("Saved {}", self.patch_path),
+                        Err(err) => format!("Save failed: {}", err),
+                    };
+                }
+                if ui.button("Open").clicked() {
+                    self.export_status = match self.open_patch() {
+                        Ok(()) => format!("Opened {}", self.patch_path),
+                        Err(err) => format!("Open failed: {}", err),
+                    };
+                }
+                ui.label(&self.export_status);
             });
         });
         let graph_response = egui::CentralPanel::default()
             .show(ctx, |ui| {
                 self.state.draw_graph_editor(
                     ui,
-                    AllMyNodeTemplates,
+                    AllMyNodeTemplates { registry: &self.registry },
                     &mut self.user_state,
                     Vec::default(),
                 )
@@ -633,36 +618,60 @@ impl eframe::App for NodeGraphExample {
             // Here, we ignore all other graph events. But you may find
             // some use for them. For example, by playing a sound when a new
             // connection is created
+            if let NodeResponse::DeleteNodeFull { node_id, .. } = node_response {
+                // A deleted output can't stay soloed, it would keep every
+                // other output silent.
+                self.user_state.muted.remove(&node_id);
+                if self.user_state.soloed.remove(&node_id) && self.playing_outputs {
+                    self.play_outputs();
+                }
+                if self.user_state.held_note == Some(node_id) {
+                    self.user_state.held_note = None;
+                    self.playback_error = self.remote.note_off().err().map(|err| format!("Failed to stop note: {}", err));
+                }
+            }
             if let NodeResponse::User(user_event) = node_response {
                 match user_event {
                     MyResponse::SetActiveNode(node) => {
-                        println!("start");
-                        self.user_state.active_node = Some(node);
-                        let stream = evaluate_node(&self.state.graph, node, &mut HashMap::new())
-                            .map(|value| {
-                                match value {
-                                    MyValueType::Stream { value } => value,
-                                    _ => fm::Stream::Empty(fm::Empty::new()),
-                                }
-                            }).expect("i dont know what to do");
-                        println!("fetched stream");
-                        self.sink.skip_one();
-                        println!("stopped sink");
-                        self.sink.append(stream);
-                        println!("started stream");
+                        self.play_active(node);
                     },
                     MyResponse::ClearActiveNode => {
-                        self.sink.stop();
+                        self.stop();
                         self.user_state.active_node = None;
                     }
+                    MyResponse::ToggleMute(node) => {
+                        if !self.user_state.muted.remove(&node) {
+                            self.user_state.muted.insert(node);
+                        }
+                        if self.playing_outputs {
+                            self.play_outputs();
+                        }
+                    }
+                    MyResponse::ToggleSolo(node) => {
+                        if !self.user_state.soloed.remove(&node) {
+                            self.user_state.soloed.insert(node);
+                        }
+                        if self.playing_outputs {
+                            self.play_outputs();
+                        }
+                    }
+                    MyResponse::SetParameter(node) => {
+                        self.playback_error = self.set_parameter(node).err().map(|err| format!("Failed to set parameter: {}", err));
+                    }
+                    MyResponse::NoteOn(_) => {
+                        self.playback_error = self.remote.note_on(60, 1.0).err().map(|err| format!("Failed to play note: {}", err));
+                    }
+                    MyResponse::NoteOff(_) => {
+                        self.playback_error = self.remote.note_off().err().map(|err| format!("Failed to stop note: {}", err));
+                    }
                 }
             }
         }
 
         if let Some(node) = self.user_state.active_node {
             if self.state.graph.nodes.contains_key(node) {
-                let text = match evaluate_node(&self.state.graph, node, &mut HashMap::new()) {
-                    Ok(value) => format!("The result is: {:?}", value),
+                let text = match plan_for(&self.state.graph, node, self.sample_rate) {
+                    Ok(compiled) => format!("The result is a plan of {} steps", compiled.plan.steps()),
                     Err(err) => format!("Execution error: {}", err),
                 };
                 ctx.debug_painter().text(
@@ -676,194 +685,14 @@ impl eframe::App for NodeGraphExample {
                 self.user_state.active_node = None;
             }
         }
-    }
-}
-
-type OutputsCache = HashMap<OutputId, MyValueType>;
-
-/// Recursively evaluates all dependencies of this node, then evaluates the node itself.
-pub fn evaluate_node(
-    graph: &MyGraph,
-    node_id: NodeId,
-    outputs_cache: &mut OutputsCache,
-) -> anyhow::Result<MyValueType> {
-    // To solve a similar problem as creating node types above, we define an
-    // Evaluator as a convenience. It may be overkill for this small example,
-    // but something like this makes the code much more readable when the
-    // number of nodes starts growing.
-
-    struct Evaluator<'a> {
-        graph: &'a MyGraph,
-        outputs_cache: &'a mut OutputsCache,
-        node_id: NodeId,
-    }
-    impl<'a> Evaluator<'a> {
-        fn new(graph: &'a MyGraph, outputs_cache: &'a mut OutputsCache, node_id: NodeId) -> Self {
-            Self {
-                graph,
-                outputs_cache,
-                node_id,
-            }
-        }
-        fn evaluate_input(&mut self, name: &str) -> anyhow::Result<MyValueType> {
-            // Calling `evaluate_input` recursively evaluates other nodes in the
-            // graph until the input value for a paramater has been computed.
-            evaluate_input(self.graph, self.node_id, name, self.outputs_cache)
-        }
-        fn populate_output(
-            &mut self,
-            name: &str,
-            value: MyValueType,
-        ) -> anyhow::Result<MyValueType> {
-            // After computing an output, we don't just return it, but we also
-            // populate the outputs cache with it. This ensures the evaluation
-            // only ever computes an output once.
-            //
-            // The return value of the function is the "final" output of the
-            // node, the thing we want to get from the evaluation. The example
-            // would be slightly more contrived when we had multiple output
-            // values, as we would need to choose which of the outputs is the
-            // one we want to return. Other outputs could be used as
-            // intermediate values.
-            //
-            // Note that this is just one possible semantic interpretation of
-            // the graphs, you can come up with your own evaluation semantics!
-            populate_output(self.graph, self.outputs_cache, self.node_id, name, value)
-        }
-        fn input_stream(&mut self, name: &str) -> anyhow::Result<fm::Stream> {
-            self.evaluate_input(name)?.try_to_stream()
-        }
-        fn input_const(&mut self, name: &str) -> anyhow::Result<f32> {
-            self.evaluate_input(name)?.try_to_const()
-        }
-        fn output_stream(&mut self, name: &str, value: fm::Stream) -> anyhow::Result<MyValueType> {
-            self.populate_output(name, MyValueType::Stream { value })
-        }
-        fn output_const(&mut self, name: &str, value: f32) -> anyhow::Result<MyValueType> {
-            self.populate_output(name, MyValueType::Const { value })
-        }
-    }
-
-    let node = &graph[node_id];
-    let mut evaluator = Evaluator::new(graph, outputs_cache, node_id);
-    match node.user_data.template.clone() {
-        fm::Stream::SineWave(mut wave) => {
-            wave.set_frequency(evaluator.input_const("Frequency")?);
-            wave.set_phase_shift(evaluator.input_const("Phase Shift")?);
-            evaluator.output_stream("Stream", fm::Stream::SineWave(wave))
-        }
-        fm::Stream::SquareWave(mut wave) => {
-            wave.set_frequency(evaluator.input_const("Frequency")?);
-            wave.set_phase_shift(evaluator.input_const("Phase Shift")?);
-            evaluator.output_stream("Stream", fm::Stream::SquareWave(wave))
-        }
-        fm::Stream::TriangleWave(mut wave) => {
-            wave.set_frequency(evaluator.input_const("Frequency")?);
-            wave.set_phase_shift(evaluator.input_const("Phase Shift")?);
-            evaluator.output_stream("Stream", fm::Stream::TriangleWave(wave))
-        }
-        fm::Stream::SawtoothWave(mut wave) => {
-            wave.set_frequency(evaluator.input_const("Frequency")?);
-            wave.set_phase_shift(evaluator.input_const("Phase Shift")?);
-            evaluator.output_stream("Stream", fm::Stream::SawtoothWave(wave))
-        }
-        fm::Stream::ModulatedSineWave(mut wave) => {
-            wave.set_frequency(evaluator.input_const("Frequency")?);
-            wave.set_modulator(evaluator.input_stream("Modulation")?);
-
-            evaluator.output_stream("Stream", fm::Stream::ModulatedSineWave(wave))
-        }
-        fm::Stream::Mix(mut wave) => {
-            wave.set_stream_a(evaluator.input_stream("A")?);
-            wave.set_stream_b(evaluator.input_stream("B")?);
-            wave.set_p(evaluator.input_const("p")?);
-
-            evaluator.output_stream("Stream", fm::Stream::Mix(wave))
-        }
-        fm::Stream::Const(mut wave) => {
-            wave.set_val(evaluator.input_const("Value")?);
-
-            evaluator.output_stream("Stream", fm::Stream::Const(wave))
-        }
-        fm::Stream::Empty(wave) => {
-            evaluator.output_stream("Stream", fm::Stream::Empty(wave))
-        }
-        fm::Stream::Envelope(mut wave) => {
-            wave.set_stream(evaluator.input_stream("Stream")?);
-            wave.set_ad(evaluator.input_const("Attack Duration")?);
-            wave.set_a(evaluator.input_const("Attack Amplitude")?);
-            wave.set_dd(evaluator.input_const("Decay Duration")?);
-            wave.set_sd(evaluator.input_const("Sustain Duration")?);
-            wave.set_s(evaluator.input_const("Sustain Amplitude")?);
-            wave.set_rd(evaluator.input_const("Release Duration")?);
-
-            evaluator.output_stream("Stream", fm::Stream::Envelope(wave))
-        }
-        fm::Stream::WhiteNoise(wave) => {
-            evaluator.output_stream("Stream", fm::Stream::WhiteNoise(wave))
-        }
-        fm::Stream::Perlin(mut wave) => {
-            wave.set_scale(evaluator.input_const("Scale")?);
-            evaluator.output_stream("Stream", fm::Stream::Perlin(wave))
-        }
-        fm::Stream::Add(mut wave) => {
-            wave.set_stream_a(evaluator.input_stream("A")?);
-            wave.set_stream_b(evaluator.input_stream("B")?);
-
-            evaluator.output_stream("Stream", fm::Stream::Add(wave))
-        }fm::Stream::Multiply(mut wave) => {
-            wave.set_stream_a(evaluator.input_stream("A")?);
-            wave.set_stream_b(evaluator.input_stream("B")?);
-
-            evaluator.output_stream("Stream", fm::Stream::Multiply(wave))
-        }
-    }
-}
-
-fn populate_output(
-    graph: &MyGraph,
-    outputs_cache: &mut OutputsCache,
-    node_id: NodeId,
-    param_name: &str,
-    value: MyValueType,
-) -> anyhow::Result<MyValueType> {
-    let output_id = graph[node_id].get_output(param_name)?;
-    outputs_cache.insert(output_id, value.clone());
-    Ok(value)
-}
-
-// Evaluates the input value of
-fn evaluate_input(
-    graph: &MyGraph,
-    node_id: NodeId,
-    param_name: &str,
-    outputs_cache: &mut OutputsCache,
-) -> anyhow::Result<MyValueType> {
-    let input_id = graph[node_id].get_input(param_name)?;
-
-    // The output of another node is connected.
-    if let Some(other_output_id) = graph.connection(input_id) {
-        // The value was already computed due to the evaluation of some other
-        // node. We simply return value from the cache.
-        if let Some(other_value) = outputs_cache.get(&other_output_id) {
-            Ok(other_value.clone())
-        }
-        // This is the first time encountering this node, so we need to
-        // recursively evaluate it.
-        else {
-            // Calling this will populate the cache
-            evaluate_node(graph, graph[other_output_id].node, outputs_cache)?;
-
-            // Now that we know the value is cached, return it
-            Ok(outputs_cache
-                .get(&other_output_id)
-                .expect("Cache should be populated")
-                .clone()
-            )
+        if let Some(err) = &self.playback_error {
+            ctx.debug_painter().text(
+                egui::pos2(10.0, 55.0),
+                egui::Align2::LEFT_TOP,
+                err,
+                TextStyle::Button.resolve(&ctx.style()),
+                egui::Color32::WHITE,
+            );
         }
     }
-    // No existing connection, take the inline value instead.
-    else {
-        Ok(graph[input_id].value.clone())
-    }
 }
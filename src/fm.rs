@@ -1,8 +1,11 @@
-use rodio::{OutputStream, Sink, source::Source};
+use rodio::source::Source;
+use std::collections::{HashMap, VecDeque};
 use std::f32::consts::PI;
 use std::iter::Iterator;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
 use noise::{self, NoiseFn};
-use rand;
+use crate::node::{Port, SynthNode};
 // use plotters::prelude::*;
 
 #[derive(Debug, Clone)]
@@ -11,6 +14,27 @@ pub enum Stream {
     SquareWave ( SquareWave ),
     TriangleWave ( TriangleWave ),
     SawtoothWave ( SawtoothWave ),
+    Wavetable ( Wavetable ),
+    Sampler ( Sampler ),
+    Pluck ( Pluck ),
+    Additive ( Additive ),
+    Supersaw ( Supersaw ),
+    Granular ( Granular ),
+    Biquad ( Biquad ),
+    StateVariableFilter ( StateVariableFilter ),
+    LadderFilter ( LadderFilter ),
+    Delay ( Delay ),
+    Reverb ( Reverb ),
+    Chorus ( Chorus ),
+    Flanger ( Flanger ),
+    Phaser ( Phaser ),
+    Distortion ( Distortion ),
+    Bitcrusher ( Bitcrusher ),
+    Compressor ( Compressor ),
+    Limiter ( Limiter ),
+    Pan ( Pan ),
+    Width ( Width ),
+    Output ( Output ),
     ModulatedSineWave ( ModulatedSineWave ),
     Mix ( Mix ),
     Const ( Const ),
@@ -18,8 +42,10 @@ pub enum Stream {
     Envelope ( Envelope ),
     Perlin ( Perlin ),
     WhiteNoise ( WhiteNoise ),
-    Add ( Add ),
-    Multiply ( Multiply ),
+    Parameter ( Parameter ),
+    Note ( Note ),
+    Custom ( Custom ),
+    Bus ( Bus ),
 }
 
 impl Iterator for Stream {
@@ -31,15 +57,38 @@ impl Iterator for Stream {
             Self::SquareWave(s) => s.next(),
             Self::TriangleWave(s) => s.next(),
             Self::SawtoothWave(s) => s.next(),
+            Self::Wavetable(s) => s.next(),
+            Self::Sampler(s) => s.next(),
+            Self::Pluck(s) => s.next(),
+            Self::Additive(s) => s.next(),
+            Self::Supersaw(s) => s.next(),
+            Self::Granular(s) => s.next(),
+            Self::Biquad(s) => s.next(),
+            Self::StateVariableFilter(s) => s.next(),
+            Self::LadderFilter(s) => s.next(),
+            Self::Delay(s) => s.next(),
+            Self::Reverb(s) => s.next(),
+            Self::Chorus(s) => s.next(),
+            Self::Flanger(s) => s.next(),
+            Self::Phaser(s) => s.next(),
+            Self::Distortion(s) => s.next(),
+            Self::Bitcrusher(s) => s.next(),
+            Self::Compressor(s) => s.next(),
+            Self::Limiter(s) => s.next(),
+            Self::Pan(s) => s.next(),
+            Self::Width(s) => s.next(),
+            Self::Output(s) => s.next(),
             Self::ModulatedSineWave(s) => s.next(),
             Self::Mix(s) => s.next(),
             Self::Const(s) => s.next(),
             Self::Envelope ( s ) => s.next(),
             Self::Perlin ( s ) => s.next(),
             Self::WhiteNoise ( s ) => s.next(),
+            Self::Parameter(s) => s.next(),
+            Self::Note(s) => s.next(),
             Self::Empty (s) => s.next(),
-            Self::Add ( s ) => s.next(),
-            Self::Multiply (s) => s.next(),
+            Self::Custom(s) => s.next(),
+            Self::Bus(s) => s.next(),
         }
     }
 }
@@ -50,8 +99,144 @@ impl Default for Stream {
     }
 }
 
+impl Stream {
+    // Fills `out` with the next samples and returns how many were written,
+    // fewer than asked once the stream has ended.
+    //
+    // The match happens once per block, each node then runs its own
+    // `next` in a loop reading its inputs from their buffers.
+    pub fn process(&mut self, out: &mut [f32]) -> usize {
+        match self {
+            Self::SineWave(s) => fill(s, out),
+            Self::SquareWave(s) => fill(s, out),
+            Self::TriangleWave(s) => fill(s, out),
+            Self::SawtoothWave(s) => fill(s, out),
+            Self::Wavetable(s) => fill(s, out),
+            Self::Sampler(s) => fill(s, out),
+            Self::Pluck(s) => fill(s, out),
+            Self::Additive(s) => fill(s, out),
+            Self::Supersaw(s) => fill(s, out),
+            Self::Granular(s) => fill(s, out),
+            Self::Biquad(s) => fill(s, out),
+            Self::StateVariableFilter(s) => fill(s, out),
+            Self::LadderFilter(s) => fill(s, out),
+            Self::Delay(s) => fill(s, out),
+            Self::Reverb(s) => fill(s, out),
+            Self::Chorus(s) => fill(s, out),
+            Self::Flanger(s) => fill(s, out),
+            Self::Phaser(s) => fill(s, out),
+            Self::Distortion(s) => fill(s, out),
+            Self::Bitcrusher(s) => fill(s, out),
+            Self::Compressor(s) => fill(s, out),
+            Self::Limiter(s) => fill(s, out),
+            Self::Pan(s) => fill(s, out),
+            Self::Width(s) => fill(s, out),
+            Self::Output(s) => fill(s, out),
+            Self::ModulatedSineWave(s) => fill(s, out),
+            Self::Mix(s) => fill(s, out),
+            Self::Const(s) => fill(s, out),
+            Self::Empty(s) => fill(s, out),
+            Self::Envelope(s) => fill(s, out),
+            Self::Perlin(s) => fill(s, out),
+            Self::WhiteNoise(s) => fill(s, out),
+            Self::Parameter(s) => fill(s, out),
+            Self::Note(s) => fill(s, out),
+            Self::Custom(s) => fill(s, out),
+            Self::Bus(s) => fill(s, out),
+        }
+    }
+
+    // Fills a block for each output of the node, all as long as the length
+    // returned. Only Custom nodes write more than one.
+    fn process_outputs(&mut self, outputs: &mut [Vec<f32>]) -> usize {
+        match self {
+            Self::Custom(s) => s.run(outputs),
+            _ => self.process(&mut outputs[0]),
+        }
+    }
+
+    // Sets the rate a node runs at. Only this node is changed, its inputs
+    // are set when they are evaluated themselves.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        match self {
+            Self::SineWave(s) => s.sample_rate = sample_rate,
+            Self::SquareWave(s) => s.sample_rate = sample_rate,
+            Self::TriangleWave(s) => s.sample_rate = sample_rate,
+            Self::SawtoothWave(s) => s.sample_rate = sample_rate,
+            Self::Wavetable(s) => s.sample_rate = sample_rate,
+            Self::Sampler(s) => s.sample_rate = sample_rate,
+            Self::Pluck(s) => s.sample_rate = sample_rate,
+            Self::Additive(s) => s.sample_rate = sample_rate,
+            Self::Supersaw(s) => s.sample_rate = sample_rate,
+            Self::Granular(s) => s.sample_rate = sample_rate,
+            Self::Biquad(s) => s.sample_rate = sample_rate,
+            Self::StateVariableFilter(s) => s.sample_rate = sample_rate,
+            Self::LadderFilter(s) => s.sample_rate = sample_rate,
+            Self::Delay(s) => s.sample_rate = sample_rate,
+            Self::Reverb(s) => s.sample_rate = sample_rate,
+            Self::Chorus(s) => s.sample_rate = sample_rate,
+            Self::Flanger(s) => s.sample_rate = sample_rate,
+            Self::Phaser(s) => s.sample_rate = sample_rate,
+            Self::Distortion(s) => s.sample_rate = sample_rate,
+            Self::Bitcrusher(s) => s.sample_rate = sample_rate,
+            Self::Compressor(s) => s.sample_rate = sample_rate,
+            Self::Limiter(s) => s.sample_rate = sample_rate,
+            Self::Pan(s) => s.sample_rate = sample_rate,
+            Self::Width(s) => s.sample_rate = sample_rate,
+            Self::Output(s) => s.sample_rate = sample_rate,
+            Self::ModulatedSineWave(s) => s.sample_rate = sample_rate,
+            Self::Mix(s) => s.sample_rate = sample_rate,
+            Self::Const(s) => s.sample_rate = sample_rate,
+            Self::Empty(s) => s.sample_rate = sample_rate,
+            Self::Envelope(s) => s.sample_rate = sample_rate,
+            Self::Perlin(s) => s.sample_rate = sample_rate,
+            Self::WhiteNoise(s) => s.sample_rate = sample_rate,
+            Self::Parameter(s) => s.sample_rate = sample_rate,
+            Self::Note(s) => s.sample_rate = sample_rate,
+            Self::Custom(s) => s.sample_rate = sample_rate,
+            Self::Bus(s) => s.sample_rate = sample_rate,
+        }
+    }
+
+    // Calls `f` with every input of this node.
+    fn for_each_input(&mut self, f: &mut impl FnMut(&mut Input)) {
+        match self {
+            Self::SineWave(s) => f(&mut s.sync),
+            Self::SquareWave(s) => f(&mut s.sync),
+            Self::TriangleWave(s) => f(&mut s.sync),
+            Self::SawtoothWave(s) => f(&mut s.sync),
+            Self::Wavetable(s) => f(&mut s.position),
+            Self::Sampler(s) => { f(&mut s.rate); f(&mut s.gate); }
+            Self::Pluck(s) => f(&mut s.trigger),
+            Self::Granular(s) => { f(&mut s.input); f(&mut s.position); }
+            Self::Biquad(s) => { f(&mut s.stream); f(&mut s.cutoff); f(&mut s.q); }
+            Self::StateVariableFilter(s) => { f(&mut s.stream); f(&mut s.cutoff); f(&mut s.q); }
+            Self::LadderFilter(s) => { f(&mut s.stream); f(&mut s.cutoff); f(&mut s.resonance); }
+            Self::Delay(s) => { f(&mut s.stream); f(&mut s.time); }
+            Self::Reverb(s) => f(&mut s.stream),
+            Self::Chorus(s) => { f(&mut s.stream); f(&mut s.delay.lfo.external); }
+            Self::Flanger(s) => { f(&mut s.stream); f(&mut s.delay.lfo.external); }
+            Self::Phaser(s) => { f(&mut s.stream); f(&mut s.lfo.external); }
+            Self::Distortion(s) => { f(&mut s.stream); f(&mut s.drive); }
+            Self::Bitcrusher(s) => { f(&mut s.stream); f(&mut s.rate); }
+            Self::Compressor(s) => { f(&mut s.stream); f(&mut s.sidechain); }
+            Self::Limiter(s) => { f(&mut s.stream); f(&mut s.sidechain); }
+            Self::Pan(s) => { f(&mut s.stream); f(&mut s.pan); }
+            Self::Width(s) => { f(&mut s.left); f(&mut s.right); }
+            Self::Output(s) => { f(&mut s.left); f(&mut s.right); }
+            Self::ModulatedSineWave(s) => f(&mut s.modulator),
+            Self::Mix(s) => { f(&mut s.stream_a); f(&mut s.stream_b); }
+            Self::Envelope(s) => f(&mut s.stream),
+            Self::Custom(s) => s.inputs.iter_mut().for_each(f),
+            Self::Additive(_) | Self::Supersaw(_) | Self::Const(_) | Self::Empty(_)
+            | Self::Perlin(_) | Self::WhiteNoise(_) | Self::Parameter(_) | Self::Note(_)
+            | Self::Bus(_) => {}
+        }
+    }
+}
+
 impl Source for Stream {
-    fn current_frame_len(&self) -> Option<usize> {
+    fn current_span_len(&self) -> Option<usize> {
         None
     }
 
@@ -65,15 +250,38 @@ impl Source for Stream {
             Self::SquareWave(s) => s.sample_rate(),
             Self::TriangleWave(s) => s.sample_rate(),
             Self::SawtoothWave(s) => s.sample_rate(),
+            Self::Wavetable(s) => s.sample_rate(),
+            Self::Sampler(s) => s.sample_rate(),
+            Self::Pluck(s) => s.sample_rate(),
+            Self::Additive(s) => s.sample_rate(),
+            Self::Supersaw(s) => s.sample_rate(),
+            Self::Granular(s) => s.sample_rate(),
+            Self::Biquad(s) => s.sample_rate(),
+            Self::StateVariableFilter(s) => s.sample_rate(),
+            Self::LadderFilter(s) => s.sample_rate(),
+            Self::Delay(s) => s.sample_rate(),
+            Self::Reverb(s) => s.sample_rate(),
+            Self::Chorus(s) => s.sample_rate(),
+            Self::Flanger(s) => s.sample_rate(),
+            Self::Phaser(s) => s.sample_rate(),
+            Self::Distortion(s) => s.sample_rate(),
+            Self::Bitcrusher(s) => s.sample_rate(),
+            Self::Compressor(s) => s.sample_rate(),
+            Self::Limiter(s) => s.sample_rate(),
+            Self::Pan(s) => s.sample_rate(),
+            Self::Width(s) => s.sample_rate(),
+            Self::Output(s) => s.sample_rate(),
             Self::ModulatedSineWave(s) => s.sample_rate(),
             Self::Mix(s) => s.sample_rate(),
             Self::Const(s) => s.sample_rate(),
             Self::Envelope ( s ) => s.sample_rate(),
             Self::Perlin ( s ) => s.sample_rate(),
             Self::WhiteNoise ( s ) => s.sample_rate(),
+            Self::Parameter(s) => s.sample_rate(),
+            Self::Note(s) => s.sample_rate(),
             Self::Empty(s) => s.sample_rate(),
-            Self::Add ( s ) => s.sample_rate(),
-            Self::Multiply (s) => s.sample_rate(),
+            Self::Custom(s) => s.sample_rate(),
+            Self::Bus(s) => s.sample_rate(),
         }
     }
 
@@ -82,6 +290,90 @@ impl Source for Stream {
     }
 }
 
+fn fill(node: &mut impl Iterator<Item = f32>, out: &mut [f32]) -> usize {
+    for (i, slot) in out.iter_mut().enumerate() {
+        match node.next() {
+            Some(x) => *slot = x,
+            None => return i,
+        }
+    }
+    out.len()
+}
+
+// Number of samples pulled through the graph at a time.
+pub const BLOCK_SIZE: usize = 256;
+
+// An input of a node. The stream connected to it is processed a block at a
+// time into a buffer that the node then reads sample by sample. Boxed so
+// that nodes with many inputs stay small.
+#[derive(Clone, Default)]
+struct Input(Box<InputState>);
+
+#[derive(Clone, Default)]
+struct InputState {
+    stream: Stream,
+    block: Vec<f32>,
+    position: usize,
+    len: usize,
+    ended: bool,
+}
+
+impl Input {
+    fn new(stream: Stream) -> Self {
+        // Fed inputs get their buffer up front, so running a plan never allocates.
+        let block = match stream {
+            Stream::Bus(_) => Vec::with_capacity(BLOCK_SIZE),
+            _ => Vec::new(),
+        };
+        Self(Box::new(InputState { stream, block, ..InputState::default() }))
+    }
+
+    fn set(&mut self, stream: Stream) {
+        *self = Self::new(stream);
+    }
+
+    fn next(&mut self) -> Option<f32> {
+        let input = &mut *self.0;
+        if input.position == input.len {
+            if input.ended {
+                return None;
+            }
+            // The buffer is allocated on first use, like the delay lines.
+            if input.block.is_empty() {
+                input.block = vec![0.0; BLOCK_SIZE];
+            }
+            input.len = input.stream.process(&mut input.block);
+            input.ended = input.len < BLOCK_SIZE;
+            input.position = 0;
+            if input.len == 0 {
+                return None;
+            }
+        }
+        input.position += 1;
+        Some(input.block[input.position - 1])
+    }
+
+    // Hands the input the block its bus points to, when it is wired to an
+    // earlier step of a plan.
+    fn feed(&mut self, blocks: &[Vec<f32>], lens: &[usize]) {
+        let input = &mut *self.0;
+        if let Stream::Bus(bus) = &input.stream {
+            let block = &blocks[bus.slot][..lens[bus.slot]];
+            input.block.clear();
+            input.block.extend_from_slice(block);
+            input.position = 0;
+            input.len = block.len();
+            input.ended = block.len() < BLOCK_SIZE;
+        }
+    }
+}
+
+impl std::fmt::Debug for Input {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.stream.fmt(f)
+    }
+}
+
 // A struct that generates a sine wave at a given frequency and sample rate.
 #[derive(Debug, Clone)]
 pub struct SineWave {
@@ -89,6 +381,8 @@ pub struct SineWave {
     sample_rate: u32,
     current_sample: u32,
     phase_shift: f32,
+    sync: Input,
+    sync_edge: Trigger,
 }
 
 impl SineWave {
@@ -98,6 +392,8 @@ impl SineWave {
             sample_rate: 44100,
             current_sample: 0,
             phase_shift: 0f32,
+            sync: Input::default(),
+            sync_edge: Trigger::default(),
         }
     }
     
@@ -108,21 +404,33 @@ impl SineWave {
     pub fn set_phase_shift(&mut self, shift: f32) {
         self.phase_shift = shift;
     }
+
+    // Restarts the wave whenever the sync stream crosses zero upwards.
+    pub fn set_sync(&mut self, sync: Stream) {
+        self.sync.set(sync);
+    }
+}
+
+fn sine_wave(x: f32) -> f32 {
+    (x * 2.0 * PI).sin()
 }
 
 impl Iterator for SineWave {
     type Item = f32;
 
     fn next(&mut self) -> Option<f32> {
+        if self.sync_edge.rising(self.sync.next().unwrap_or(0.0)) {
+            self.current_sample = 0;
+        }
         // Compute the next sample in the sine wave
-        let sample = ((self.current_sample as f32 + self.phase_shift) * 2.0 * PI * self.frequency / self.sample_rate as f32).sin();
+        let sample = sine_wave((self.current_sample as f32 + self.phase_shift) * self.frequency / self.sample_rate as f32);
         self.current_sample += 1;
         Some(sample)
     }
 }
 
 impl Source for SineWave {
-    fn current_frame_len(&self) -> Option<usize> {
+    fn current_span_len(&self) -> Option<usize> {
         None
     }
 
@@ -146,6 +454,8 @@ pub struct SquareWave {
     sample_rate: u32,
     current_sample: u32,
     phase_shift: f32,
+    sync: Input,
+    sync_edge: Trigger,
 }
 
 impl SquareWave {
@@ -155,6 +465,8 @@ impl SquareWave {
             sample_rate: 44100,
             current_sample: 0,
             phase_shift: 0f32,
+            sync: Input::default(),
+            sync_edge: Trigger::default(),
         }
     }
     
@@ -165,6 +477,11 @@ impl SquareWave {
     pub fn set_phase_shift(&mut self, shift: f32) {
         self.phase_shift = shift;
     }
+
+    // Restarts the wave whenever the sync stream crosses zero upwards.
+    pub fn set_sync(&mut self, sync: Stream) {
+        self.sync.set(sync);
+    }
 }
 
 fn square_wave(x: f32) -> f32 {
@@ -176,6 +493,9 @@ impl Iterator for SquareWave {
     type Item = f32;
 
     fn next(&mut self) -> Option<f32> {
+        if self.sync_edge.rising(self.sync.next().unwrap_or(0.0)) {
+            self.current_sample = 0;
+        }
         // Compute the next sample in the sine wave
         let sample = square_wave((self.current_sample as f32 + self.phase_shift) * self.frequency / self.sample_rate as f32);
         self.current_sample += 1;
@@ -184,7 +504,7 @@ impl Iterator for SquareWave {
 }
 
 impl Source for SquareWave {
-    fn current_frame_len(&self) -> Option<usize> {
+    fn current_span_len(&self) -> Option<usize> {
         None
     }
 
@@ -208,6 +528,8 @@ pub struct TriangleWave {
     sample_rate: u32,
     current_sample: u32,
     phase_shift: f32,
+    sync: Input,
+    sync_edge: Trigger,
 }
 
 impl TriangleWave {
@@ -217,6 +539,80 @@ impl TriangleWave {
             sample_rate: 44100,
             current_sample: 0,
             phase_shift: 0f32,
+            sync: Input::default(),
+            sync_edge: Trigger::default(),
+        }
+    }
+    
+    pub fn set_frequency(&mut self, freq: f32) {
+        self.frequency = freq;
+    }
+
+    pub fn set_phase_shift(&mut self, shift: f32) {
+        self.phase_shift = shift;
+    }
+
+    // Restarts the wave whenever the sync stream crosses zero upwards.
+    pub fn set_sync(&mut self, sync: Stream) {
+        self.sync.set(sync);
+    }
+}
+
+fn triangle_wave(x: f32) -> f32 {
+    4.0 * (x + 0.25 - (x + 0.75).floor()).abs() - 1.0
+}
+
+impl Iterator for TriangleWave {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.sync_edge.rising(self.sync.next().unwrap_or(0.0)) {
+            self.current_sample = 0;
+        }
+        // Compute the next sample in the sine wave
+        let sample = triangle_wave((self.current_sample as f32 + self.phase_shift) * self.frequency / self.sample_rate as f32);
+        self.current_sample += 1;
+        Some(sample)
+    }
+}
+
+impl Source for TriangleWave {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1 // Mono sound
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SawtoothWave {
+    frequency: f32,
+    sample_rate: u32,
+    current_sample: u32,
+    phase_shift: f32,
+    sync: Input,
+    sync_edge: Trigger,
+}
+
+impl SawtoothWave {
+    pub fn new() -> Self {
+        Self {
+            frequency: 0f32,
+            sample_rate: 44100,
+            current_sample: 0,
+            phase_shift: 0f32,
+            sync: Input::default(),
+            sync_edge: Trigger::default(),
         }
     }
     
@@ -224,28 +620,2840 @@ impl TriangleWave {
         self.frequency = freq;
     }
 
-    pub fn set_phase_shift(&mut self, shift: f32) {
-        self.phase_shift = shift;
+    pub fn set_phase_shift(&mut self, shift: f32) {
+        self.phase_shift = shift;
+    }
+
+    // Restarts the wave whenever the sync stream crosses zero upwards.
+    pub fn set_sync(&mut self, sync: Stream) {
+        self.sync.set(sync);
+    }
+}
+
+fn sawtooth_wave(x: f32) -> f32 {
+    x % 1.0
+}
+
+impl Iterator for SawtoothWave {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.sync_edge.rising(self.sync.next().unwrap_or(0.0)) {
+            self.current_sample = 0;
+        }
+        // Compute the next sample in the sine wave
+        let sample = sawtooth_wave((self.current_sample as f32 + self.phase_shift) * self.frequency / self.sample_rate as f32);
+        self.current_sample += 1;
+        Some(sample)
+    }
+}
+
+impl Source for SawtoothWave {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1 // Mono sound
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+
+// Number of samples in the most detailed mip level of a wavetable frame, and
+// the frame size expected when slicing a wav file into frames.
+const TABLE_SIZE: usize = 2048;
+// Higher mip levels shrink along with their harmonics, but never below this.
+const MIN_TABLE_SIZE: usize = 64;
+const MAX_TABLE_FRAMES: usize = 256;
+
+// In-place radix-2 FFT, the length of `buf` has to be a power of two.
+fn fft(buf: &mut [(f32, f32)], inverse: bool) {
+    let n = buf.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j { buf.swap(i, j); }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = if inverse { 2.0 * PI / len as f32 } else { -2.0 * PI / len as f32 };
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (wr, wi) = ((angle * k as f32).cos(), (angle * k as f32).sin());
+                let (ar, ai) = buf[start + k];
+                let (br, bi) = buf[start + k + len / 2];
+                let (tr, ti) = (br * wr - bi * wi, br * wi + bi * wr);
+                buf[start + k] = (ar + tr, ai + ti);
+                buf[start + k + len / 2] = (ar - tr, ai - ti);
+            }
+        }
+        len <<= 1;
+    }
+
+    if inverse {
+        for x in buf.iter_mut() {
+            x.0 /= n as f32;
+            x.1 /= n as f32;
+        }
+    }
+}
+
+// Reads a wav file and mixes it down to mono.
+fn read_wav(path: &str) -> anyhow::Result<(Vec<f32>, u32)> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    let samples: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = 1.0 / (1u64 << (spec.bits_per_sample - 1)) as f32;
+            reader.samples::<i32>().map(|s| s.map(|s| s as f32 * scale)).collect::<Result<_, _>>()?
+        }
+    };
+    let channels = spec.channels.max(1) as usize;
+    let mono = samples
+        .chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+    Ok((mono, spec.sample_rate))
+}
+
+// Renders `seconds` of each source to its own 16 bit wav file, with as many
+// channels as the source has. All files are written side by side in a
+// single pass over time.
+pub fn write_wavs<S: Source<Item = f32>>(files: Vec<(String, S)>, seconds: f32) -> anyhow::Result<()> {
+    let mut renders = files.into_iter().map(|(path, source)| {
+        let spec = hound::WavSpec {
+            channels: source.channels(),
+            sample_rate: source.sample_rate(),
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let frames = (seconds.max(0.0) * spec.sample_rate as f32) as usize;
+        let writer = hound::WavWriter::create(&path, spec)?;
+        Ok((source.take(frames * spec.channels as usize), writer))
+    }).collect::<anyhow::Result<Vec<_>>>()?;
+
+    // One frame of every file at a time, until all of them are done.
+    let mut done = false;
+    while !done {
+        done = true;
+        for (source, writer) in renders.iter_mut() {
+            for _ in 0..writer.spec().channels {
+                if let Some(sample) = source.next() {
+                    writer.write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)?;
+                    done = false;
+                }
+            }
+        }
+    }
+    for (_, writer) in renders {
+        writer.finalize()?;
+    }
+    Ok(())
+}
+
+// A set of single cycle waveforms ("frames") to morph between. Every frame is
+// stored as a chain of mip levels, each one holding half the harmonics of the
+// previous one, so that high notes can be played without aliasing.
+#[derive(Clone, Default)]
+pub struct WavetableBank {
+    // Indexed by [level][frame].
+    levels: Vec<Vec<Vec<f32>>>,
+}
+
+impl WavetableBank {
+    // Builds a bank from single cycles that are each `TABLE_SIZE` samples long.
+    fn from_frames(frames: &[Vec<f32>]) -> Self {
+        let spectra: Vec<Vec<(f32, f32)>> = frames.iter().map(|frame| {
+            let mut spectrum: Vec<(f32, f32)> = frame.iter().map(|&x| (x, 0.0)).collect();
+            fft(&mut spectrum, false);
+            spectrum
+        }).collect();
+
+        let mut levels = vec![];
+        let mut harmonics = TABLE_SIZE / 2;
+        while harmonics >= 1 {
+            let len = (harmonics * 2).max(MIN_TABLE_SIZE);
+            let scale = len as f32 / TABLE_SIZE as f32;
+            levels.push(spectra.iter().map(|spectrum| {
+                // The DC bin is dropped on purpose, some shapes (the sawtooth)
+                // aren't centered around zero.
+                let mut table = vec![(0f32, 0f32); len];
+                for h in 1..=harmonics.min(len / 2 - 1) {
+                    let (re, im) = spectrum[h];
+                    table[h] = (re * scale, im * scale);
+                    let (re, im) = spectrum[TABLE_SIZE - h];
+                    table[len - h] = (re * scale, im * scale);
+                }
+                fft(&mut table, true);
+                table.into_iter().map(|(re, _)| re).collect()
+            }).collect());
+            harmonics /= 2;
+        }
+
+        Self { levels }
+    }
+
+    // A bank generated from the basic oscillator shapes, morphing from sine
+    // over triangle and sawtooth to square.
+    pub fn shapes() -> Self {
+        let shapes: [fn(f32) -> f32; 4] = [sine_wave, triangle_wave, sawtooth_wave, square_wave];
+        let frames: Vec<Vec<f32>> = shapes.iter().map(|shape| {
+            (0..TABLE_SIZE).map(|i| shape(i as f32 / TABLE_SIZE as f32)).collect()
+        }).collect();
+        Self::from_frames(&frames)
+    }
+
+    // Loads a bank from a wav file. Files of at least `TABLE_SIZE` samples are
+    // sliced into frames of that size, anything shorter is taken as a single
+    // cycle and stretched to fit.
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let (samples, _) = read_wav(path)?;
+        if samples.is_empty() {
+            anyhow::bail!("{} contains no samples", path);
+        }
+        let frame_len = samples.len().min(TABLE_SIZE);
+        let frames: Vec<Vec<f32>> = samples
+            .chunks_exact(frame_len)
+            .take(MAX_TABLE_FRAMES)
+            .map(|cycle| (0..TABLE_SIZE).map(|i| {
+                let x = i as f32 * frame_len as f32 / TABLE_SIZE as f32;
+                let i0 = x as usize;
+                lerp(cycle[i0], cycle[(i0 + 1) % frame_len], x.fract())
+            }).collect())
+            .collect();
+        Ok(Self::from_frames(&frames))
+    }
+
+    fn frames(&self) -> usize {
+        self.levels.first().map_or(0, |level| level.len())
+    }
+
+    // Samples the bank at `phase` (in cycles), morphing between frames with a
+    // `position` in 0..1. `increment` is the phase advance per sample and
+    // selects the mip level that stays below nyquist.
+    fn sample(&self, phase: f32, position: f32, increment: f32) -> f32 {
+        let frames = self.frames();
+        if frames == 0 { return 0.0; }
+
+        let mut level = 0;
+        while level + 1 < self.levels.len() && ((TABLE_SIZE / 2) >> level) as f32 * increment.abs() > 0.5 {
+            level += 1;
+        }
+
+        let read = |table: &[f32]| {
+            let x = phase * table.len() as f32;
+            let i0 = (x as usize).min(table.len() - 1);
+            lerp(table[i0], table[(i0 + 1) % table.len()], x.fract())
+        };
+        let x = position.clamp(0.0, 1.0) * (frames - 1) as f32;
+        let f0 = x as usize;
+        let f1 = (f0 + 1).min(frames - 1);
+        let tables = &self.levels[level];
+        lerp(read(&tables[f0]), read(&tables[f1]), x.fract())
+    }
+}
+
+impl std::fmt::Debug for WavetableBank {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WavetableBank")
+            .field("frames", &self.frames())
+            .field("levels", &self.levels.len())
+            .finish()
+    }
+}
+
+// A wavetable oscillator that morphs through the frames of a bank.
+#[derive(Debug, Clone)]
+pub struct Wavetable {
+    bank: Arc<WavetableBank>,
+    frequency: f32,
+    position: Input,
+    sample_rate: u32,
+    phase: f32,
+}
+
+impl Wavetable {
+    pub fn new() -> Self {
+        Self {
+            bank: Arc::default(),
+            frequency: 0f32,
+            position: Input::default(),
+            sample_rate: 44100,
+            phase: 0f32,
+        }
+    }
+
+    pub fn set_bank(&mut self, bank: Arc<WavetableBank>) {
+        self.bank = bank;
+    }
+
+    pub fn set_frequency(&mut self, freq: f32) {
+        self.frequency = freq;
+    }
+
+    pub fn set_position(&mut self, position: Stream) {
+        self.position.set(position);
+    }
+}
+
+impl Iterator for Wavetable {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        self.position.next().map(|position| {
+            let increment = self.frequency / self.sample_rate as f32;
+            let sample = self.bank.sample(self.phase, position, increment);
+            self.phase = (self.phase + increment).rem_euclid(1.0);
+            sample
+        })
+    }
+}
+
+impl Source for Wavetable {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1 // Mono sound
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+// A mono recording loaded from disk.
+#[derive(Clone, Default)]
+pub struct SampleBuffer {
+    samples: Vec<f32>,
+    sample_rate: u32,
+}
+
+impl SampleBuffer {
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let (samples, sample_rate) = read_wav(path)?;
+        Ok(Self { samples, sample_rate })
+    }
+
+    fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    // Reads between two samples with linear interpolation.
+    fn read(&self, position: f64) -> f32 {
+        if self.samples.is_empty() || position < 0.0 { return 0.0; }
+        let i0 = position as usize;
+        let s0 = self.samples.get(i0).copied().unwrap_or(0.0);
+        let s1 = self.samples.get(i0 + 1).copied().unwrap_or(0.0);
+        lerp(s0, s1, position.fract() as f32)
+    }
+}
+
+impl std::fmt::Debug for SampleBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SampleBuffer")
+            .field("samples", &self.samples.len())
+            .field("sample_rate", &self.sample_rate)
+            .finish()
+    }
+}
+
+// Detects a signal crossing zero upwards, used for gates, triggers and sync.
+#[derive(Debug, Clone, Default)]
+struct Trigger {
+    last: f32,
+}
+
+impl Trigger {
+    fn rising(&mut self, x: f32) -> bool {
+        let rising = self.last <= 0.0 && x > 0.0;
+        self.last = x;
+        rising
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopMode {
+    Off,
+    Forward,
+    PingPong,
+}
+
+impl LoopMode {
+    pub const NAMES: [&'static str; 3] = ["Off", "Forward", "Ping-Pong"];
+
+    pub fn from_index(index: usize) -> Self {
+        match index {
+            1 => Self::Forward,
+            2 => Self::PingPong,
+            _ => Self::Off,
+        }
+    }
+}
+
+// Plays back a sample between a start and end point, restarting it whenever
+// the gate goes high.
+#[derive(Debug, Clone)]
+pub struct Sampler {
+    sample: Arc<SampleBuffer>,
+    rate: Input,
+    gate: Input,
+    pitch: f32,
+    start: f32,
+    end: f32,
+    loop_mode: LoopMode,
+    sample_rate: u32,
+    position: f64,
+    direction: f64,
+    started: bool,
+    gated: bool,
+    playing: bool,
+    trigger: Trigger,
+}
+
+impl Sampler {
+    pub fn new() -> Self {
+        Self {
+            sample: Arc::default(),
+            rate: Input::default(),
+            gate: Input::default(),
+            pitch: 0f32,
+            start: 0f32,
+            end: 1f32,
+            loop_mode: LoopMode::Off,
+            sample_rate: 44100,
+            position: 0f64,
+            direction: 1f64,
+            started: false,
+            gated: false,
+            playing: false,
+            trigger: Trigger::default(),
+        }
+    }
+
+    pub fn set_sample(&mut self, sample: Arc<SampleBuffer>) { self.sample = sample; }
+    pub fn set_rate(&mut self, rate: Stream) { self.rate.set(rate); }
+    // Without a gate the sample plays once from the start, with one it
+    // waits for the first rising edge.
+    pub fn set_gate(&mut self, gate: Stream) {
+        self.gated = !matches!(gate, Stream::Empty(_));
+        self.gate.set(gate);
+    }
+    // Pitch offset in semitones, on top of the rate.
+    pub fn set_pitch(&mut self, v: f32) { self.pitch = v; }
+    // Start and end are fractions of the sample length.
+    pub fn set_start(&mut self, v: f32) { self.start = v; }
+    pub fn set_end(&mut self, v: f32) { self.end = v; }
+    pub fn set_loop_mode(&mut self, mode: LoopMode) { self.loop_mode = mode; }
+
+    fn bounds(&self) -> (f64, f64) {
+        let len = self.sample.len() as f64;
+        let a = self.start.clamp(0.0, 1.0) as f64 * len;
+        let b = self.end.clamp(0.0, 1.0) as f64 * len;
+        (a.min(b), a.max(b))
+    }
+}
+
+impl Iterator for Sampler {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let rate = self.rate.next()?;
+        let gate = self.gate.next().unwrap_or(0.0);
+        let (start, end) = self.bounds();
+
+        if self.trigger.rising(gate) || !(self.started || self.gated) {
+            self.started = true;
+            self.playing = true;
+            self.position = start;
+            self.direction = 1.0;
+        }
+        if !self.playing || end - start < 1.0 {
+            return Some(0.0);
+        }
+
+        let sample = self.sample.read(self.position);
+        let step = rate as f64
+            * 2f64.powf(self.pitch as f64 / 12.0)
+            * self.sample.sample_rate as f64 / self.sample_rate as f64;
+        self.position += step * self.direction;
+
+        let length = end - start;
+        match self.loop_mode {
+            LoopMode::Off => {
+                if self.position >= end || self.position < start { self.playing = false; }
+            }
+            LoopMode::Forward => {
+                self.position = start + (self.position - start).rem_euclid(length);
+            }
+            LoopMode::PingPong => {
+                if self.position >= end {
+                    self.position = (2.0 * end - self.position).max(start);
+                    self.direction = -self.direction;
+                } else if self.position < start {
+                    self.position = (2.0 * start - self.position).min(end);
+                    self.direction = -self.direction;
+                }
+            }
+        }
+        Some(sample)
+    }
+}
+
+impl Source for Sampler {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1 // Mono sound
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+// A Karplus-Strong plucked string. Every pluck fills a delay line one period
+// long with a burst of white noise, which then circulates through a damping
+// low-pass and slowly dies out.
+#[derive(Debug, Clone)]
+pub struct Pluck {
+    frequency: f32,
+    damping: f32,
+    brightness: f32,
+    trigger: Input,
+    sample_rate: u32,
+    noise: WhiteNoise,
+    edge: Trigger,
+    started: bool,
+    line: Vec<f32>,
+    index: usize,
+    gain: f32,
+    // State of the loop filter and the all-pass tuning the fractional part
+    // of the period.
+    last: f32,
+    allpass: f32,
+    allpass_x: f32,
+    allpass_y: f32,
+}
+
+impl Pluck {
+    pub fn new() -> Self {
+        Self {
+            frequency: 0f32,
+            damping: 0f32,
+            brightness: 0f32,
+            trigger: Input::default(),
+            sample_rate: 44100,
+            noise: WhiteNoise::new(),
+            edge: Trigger::default(),
+            started: false,
+            line: vec![],
+            index: 0,
+            gain: 0f32,
+            last: 0f32,
+            allpass: 0f32,
+            allpass_x: 0f32,
+            allpass_y: 0f32,
+        }
+    }
+
+    pub fn set_frequency(&mut self, freq: f32) { self.frequency = freq; }
+    // 0 rings for about ten seconds, 1 dies out almost immediately.
+    pub fn set_damping(&mut self, v: f32) { self.damping = v; }
+    // How much of the high end the excitation and the string keep.
+    pub fn set_brightness(&mut self, v: f32) { self.brightness = v; }
+    pub fn set_trigger(&mut self, trigger: Stream) { self.trigger.set(trigger); }
+
+    fn pluck(&mut self) {
+        let brightness = self.brightness.clamp(0.0, 1.0);
+        // Above a quarter of the rate the all-pass would need a negative
+        // fraction of a sample and the string would blow up.
+        let frequency = self.frequency.max(20.0).min(self.sample_rate as f32 / 4.0);
+        // The loop filter delays by `smoothing` samples and the all-pass
+        // takes whatever is left after the integer part of the period.
+        let smoothing = 0.5 * (1.0 - brightness);
+        let period = self.sample_rate as f32 / frequency;
+        let len = ((period - smoothing - 0.1).floor() as usize).max(1);
+        let fraction = period - smoothing - len as f32;
+        self.allpass = (1.0 - fraction) / (1.0 + fraction);
+
+        let decay = 10.0 * 0.005f32.powf(self.damping.clamp(0.0, 1.0));
+        self.gain = 10f32.powf(-3.0 / (decay * frequency));
+
+        // Refilled in place, plucking again at the same pitch never allocates.
+        let mut filtered = 0.0;
+        let noise = &mut self.noise;
+        self.line.clear();
+        self.line.extend((0..len).map(|_| {
+            filtered += brightness.max(0.05) * (noise.next().unwrap_or(0.0) - filtered);
+            filtered
+        }));
+        let mean = self.line.iter().sum::<f32>() / len as f32;
+        self.line.iter_mut().for_each(|x| *x -= mean);
+
+        self.index = 0;
+        self.last = 0.0;
+        self.allpass_x = 0.0;
+        self.allpass_y = 0.0;
+    }
+}
+
+impl Iterator for Pluck {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let trigger = self.trigger.next().unwrap_or(0.0);
+        if self.edge.rising(trigger) || !self.started {
+            self.started = true;
+            self.pluck();
+        }
+
+        let delayed = self.line[self.index];
+        let smoothing = 0.5 * (1.0 - self.brightness.clamp(0.0, 1.0));
+        let filtered = (1.0 - smoothing) * delayed + smoothing * self.last;
+        self.last = delayed;
+        let tuned = self.allpass * filtered + self.allpass_x - self.allpass * self.allpass_y;
+        self.allpass_x = filtered;
+        self.allpass_y = tuned;
+
+        self.line[self.index] = tuned * self.gain;
+        self.index = (self.index + 1) % self.line.len();
+        Some(delayed)
+    }
+}
+
+impl Source for Pluck {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1 // Mono sound
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+// A single sine component of an additive oscillator. The ratio is relative to
+// the fundamental and the detune is in cents.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Partial {
+    pub ratio: f32,
+    pub amplitude: f32,
+    pub detune: f32,
+}
+
+impl Partial {
+    pub fn new(ratio: f32, amplitude: f32) -> Self {
+        Self { ratio, amplitude, detune: 0.0 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdditivePreset {
+    Custom,
+    Saw,
+    Square,
+    Organ,
+}
+
+impl AdditivePreset {
+    pub const NAMES: [&'static str; 4] = ["Custom", "Saw", "Square", "Organ"];
+
+    pub fn from_index(index: usize) -> Self {
+        match index {
+            1 => Self::Saw,
+            2 => Self::Square,
+            3 => Self::Organ,
+            _ => Self::Custom,
+        }
+    }
+
+    // The most partials a preset spectrum has, whatever the fundamental.
+    pub const MAX_PARTIALS: usize = 1024;
+
+    // The spectrum of the preset with up to `count` partials, leaving out
+    // those at `max_ratio` times the fundamental and above, which would
+    // alias. Custom spectra come from the user, so it has none.
+    pub fn partials(self, count: usize, max_ratio: f32) -> Vec<Partial> {
+        let partials: Box<dyn Iterator<Item = Partial>> = match self {
+            Self::Custom => return vec![],
+            Self::Saw => Box::new((1..)
+                .map(|n| Partial::new(n as f32, 2.0 / (PI * n as f32)))),
+            Self::Square => Box::new((0..)
+                .map(|n| 2 * n + 1)
+                .map(|n| Partial::new(n as f32, 4.0 / (PI * n as f32)))),
+            // Drawbars of a tonewheel organ, from 16' up to 1'
+            Self::Organ => Box::new([(0.5, 0.6), (1.0, 0.8), (1.5, 0.5), (2.0, 0.5), (3.0, 0.3), (4.0, 0.3), (5.0, 0.15), (6.0, 0.15), (8.0, 0.1)]
+                .iter()
+                .map(|&(ratio, amplitude)| Partial::new(ratio, amplitude))),
+        };
+        partials
+            .take_while(|partial| partial.ratio < max_ratio)
+            .take(count.min(Self::MAX_PARTIALS))
+            .collect()
+    }
+}
+
+// A sine oscillator advanced by rotating a unit phasor, which saves calling
+// sin for every partial on every sample.
+#[derive(Debug, Clone)]
+struct Phasor {
+    re: f32,
+    im: f32,
+    cos: f32,
+    sin: f32,
+    gain: f32,
+}
+
+// Sums a set of sine partials in a single node.
+#[derive(Debug, Clone)]
+pub struct Additive {
+    frequency: f32,
+    partials: Vec<Partial>,
+    tilt: f32,
+    sample_rate: u32,
+    phasors: Vec<Phasor>,
+    ready: bool,
+}
+
+impl Additive {
+    pub fn new() -> Self {
+        Self {
+            frequency: 0f32,
+            partials: vec![],
+            tilt: 0f32,
+            sample_rate: 44100,
+            phasors: vec![],
+            ready: false,
+        }
+    }
+
+    pub fn set_frequency(&mut self, freq: f32) { self.frequency = freq; }
+    pub fn set_partials(&mut self, partials: Vec<Partial>) { self.partials = partials; }
+    // Spectral tilt in dB per octave above the fundamental.
+    pub fn set_tilt(&mut self, v: f32) { self.tilt = v; }
+
+    fn prepare(&mut self) {
+        let nyquist = self.sample_rate as f32 / 2.0;
+        self.phasors = self.partials.iter().filter_map(|partial| {
+            let freq = self.frequency * partial.ratio * 2f32.powf(partial.detune / 1200.0);
+            if partial.ratio <= 0.0 || freq.abs() >= nyquist { return None; }
+            let gain = partial.amplitude * 10f32.powf(self.tilt * partial.ratio.log2() / 20.0);
+            let angle = 2.0 * PI * freq / self.sample_rate as f32;
+            Some(Phasor { re: 1.0, im: 0.0, cos: angle.cos(), sin: angle.sin(), gain })
+        }).collect();
+        self.ready = true;
+    }
+}
+
+impl Iterator for Additive {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if !self.ready { self.prepare(); }
+        let mut sample = 0.0;
+        for p in self.phasors.iter_mut() {
+            sample += p.gain * p.im;
+            let re = p.re * p.cos - p.im * p.sin;
+            let im = p.re * p.sin + p.im * p.cos;
+            // Pull the phasor back onto the unit circle so rounding errors
+            // don't make it grow or shrink over time.
+            let norm = 1.5 - 0.5 * (re * re + im * im);
+            p.re = re * norm;
+            p.im = im * norm;
+        }
+        Some(sample)
+    }
+}
+
+impl Source for Additive {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1 // Mono sound
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+// A small xorshift generator. Unlike `rand::random` its state is cloned along
+// with the stream, so copies of a stream play back the same values.
+#[derive(Debug, Clone)]
+struct Rng(u32);
+
+impl Rng {
+    fn new() -> Self {
+        Self(rand::random::<u32>() | 1)
+    }
+
+    // A uniformly distributed value in 0..1
+    fn next_f32(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 8) as f32 / (1u32 << 24) as f32
+    }
+}
+
+// Smooths the discontinuity of a sawtooth at phase `t` advancing `dt` per sample.
+fn poly_blep(t: f32, dt: f32) -> f32 {
+    if t < dt {
+        let t = t / dt;
+        t + t - t * t - 1.0
+    } else if t > 1.0 - dt {
+        let t = (t - 1.0) / dt;
+        t * t + t + t + 1.0
+    } else {
+        0.0
+    }
+}
+
+// A stack of detuned sawtooth voices, each starting at a random phase.
+#[derive(Debug, Clone)]
+pub struct Supersaw {
+    frequency: f32,
+    voices: usize,
+    detune: f32,
+    spread: f32,
+    channel: Channel,
+    sample_rate: u32,
+    rng: Rng,
+    phases: Vec<f32>,
+    increments: Vec<f32>,
+    gains: Vec<f32>,
+    ready: bool,
+}
+
+impl Supersaw {
+    pub fn new() -> Self {
+        Self {
+            frequency: 0f32,
+            voices: 1,
+            detune: 0f32,
+            spread: 0f32,
+            channel: Channel::Left,
+            sample_rate: 44100,
+            rng: Rng::new(),
+            phases: vec![],
+            increments: vec![],
+            gains: vec![],
+            ready: false,
+        }
+    }
+
+    pub fn set_frequency(&mut self, freq: f32) { self.frequency = freq; }
+    pub fn set_voices(&mut self, voices: usize) { self.voices = voices.clamp(1, 32); }
+    // Distance in cents between the lowest and the center voice.
+    pub fn set_detune(&mut self, v: f32) { self.detune = v; }
+    // How far the voices are panned apart, from 0 (mono) to 1.
+    pub fn set_spread(&mut self, v: f32) { self.spread = v; }
+    pub fn set_channel(&mut self, channel: Channel) { self.channel = channel; }
+
+    fn prepare(&mut self) {
+        let n = self.voices;
+        let position = |i: usize| if n > 1 { 2.0 * i as f32 / (n - 1) as f32 - 1.0 } else { 0.0 };
+        self.increments = (0..n).map(|i| {
+            self.frequency * 2f32.powf(position(i) * self.detune / 1200.0) / self.sample_rate as f32
+        }).collect();
+        // Every other voice goes to the opposite side, so both sides get a
+        // mix of sharp and flat voices.
+        self.gains = (0..n).map(|i| {
+            let side = if i % 2 == 0 { 1.0 } else { -1.0 };
+            self.channel.pan_gain(side * position(i) * self.spread)
+        }).collect();
+        self.phases = (0..n).map(|_| self.rng.next_f32()).collect();
+        self.ready = true;
+    }
+}
+
+impl Iterator for Supersaw {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if !self.ready { self.prepare(); }
+        let mut sample = 0.0;
+        for ((phase, &dt), &gain) in self.phases.iter_mut().zip(&self.increments).zip(&self.gains) {
+            sample += gain * (2.0 * *phase - 1.0 - poly_blep(*phase, dt.abs()));
+            *phase = (*phase + dt).rem_euclid(1.0);
+        }
+        Some(sample / (self.voices as f32).sqrt())
+    }
+}
+
+impl Source for Supersaw {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1 // Mono sound
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrainSource {
+    Input,
+    Sample,
+}
+
+impl GrainSource {
+    pub const NAMES: [&'static str; 2] = ["Input", "Sample"];
+
+    pub fn from_index(index: usize) -> Self {
+        match index {
+            1 => Self::Sample,
+            _ => Self::Input,
+        }
+    }
+}
+
+// How much of the live input the granular node remembers, about three
+// seconds at 44.1kHz.
+const GRAIN_HISTORY: usize = 1 << 17;
+const MAX_GRAINS: usize = 128;
+
+#[derive(Debug, Clone)]
+struct Grain {
+    // Read position in samples of the source, for the live input this
+    // counts from the first sample ever written.
+    position: f64,
+    step: f64,
+    age: usize,
+    length: usize,
+}
+
+// Scatters short, windowed grains read from either the buffered input
+// stream or a loaded sample.
+#[derive(Debug, Clone)]
+pub struct Granular {
+    input: Input,
+    sample: Arc<SampleBuffer>,
+    source: GrainSource,
+    grain_size: f32,
+    density: f32,
+    position: Input,
+    position_jitter: f32,
+    pitch: f32,
+    pitch_jitter: f32,
+    sample_rate: u32,
+    rng: Rng,
+    history: Vec<f32>,
+    written: u64,
+    grains: Vec<Grain>,
+    countdown: f32,
+}
+
+impl Granular {
+    pub fn new() -> Self {
+        Self {
+            input: Input::default(),
+            sample: Arc::default(),
+            source: GrainSource::Input,
+            grain_size: 0f32,
+            density: 0f32,
+            position: Input::default(),
+            position_jitter: 0f32,
+            pitch: 0f32,
+            pitch_jitter: 0f32,
+            sample_rate: 44100,
+            rng: Rng::new(),
+            history: vec![],
+            written: 0,
+            grains: vec![],
+            countdown: 0f32,
+        }
+    }
+
+    pub fn set_input(&mut self, input: Stream) { self.input.set(input); }
+    pub fn set_sample(&mut self, sample: Arc<SampleBuffer>) { self.sample = sample; }
+    pub fn set_source(&mut self, source: GrainSource) { self.source = source; }
+    // Length of a grain in milliseconds.
+    pub fn set_grain_size(&mut self, v: f32) { self.grain_size = v; }
+    // Grains started per second.
+    pub fn set_density(&mut self, v: f32) { self.density = v; }
+    // Where grains start, from 0 to 1. For the live input 0 is the most
+    // recent audio and 1 the oldest.
+    pub fn set_position(&mut self, position: Stream) { self.position.set(position); }
+    pub fn set_position_jitter(&mut self, v: f32) { self.position_jitter = v; }
+    // Pitch and its random deviation per grain, in semitones.
+    pub fn set_pitch(&mut self, v: f32) { self.pitch = v; }
+    pub fn set_pitch_jitter(&mut self, v: f32) { self.pitch_jitter = v; }
+
+    fn spawn(&mut self, position: f32) {
+        if self.grains.len() >= MAX_GRAINS { return; }
+        // Room for every grain on the first spawn, later ones never allocate.
+        self.grains.reserve(MAX_GRAINS - self.grains.len());
+        let length = ((self.grain_size.max(1.0) / 1000.0 * self.sample_rate as f32) as usize).max(1);
+        let semitones = self.pitch + (self.rng.next_f32() * 2.0 - 1.0) * self.pitch_jitter;
+        let mut step = 2f64.powf(semitones as f64 / 12.0);
+        let position = (position + (self.rng.next_f32() * 2.0 - 1.0) * self.position_jitter).clamp(0.0, 1.0) as f64;
+
+        let start = match self.source {
+            GrainSource::Sample => {
+                step *= self.sample.sample_rate as f64 / self.sample_rate as f64;
+                position * self.sample.len() as f64
+            }
+            GrainSource::Input => {
+                // Stay far enough behind the write head that a grain
+                // playing faster than realtime never catches up with it.
+                let catch_up = (length as f64 * (step - 1.0)).max(0.0);
+                let delay = position * (GRAIN_HISTORY / 2) as f64 + catch_up + 1.0;
+                let delay = delay.min((GRAIN_HISTORY - 2) as f64).min(self.written as f64);
+                self.written as f64 - delay
+            }
+        };
+        self.grains.push(Grain { position: start, step, age: 0, length });
+    }
+
+    fn read(&self, position: f64) -> f32 {
+        match self.source {
+            GrainSource::Sample => self.sample.read(position),
+            GrainSource::Input => {
+                let i0 = position as usize;
+                let s0 = self.history[i0 % GRAIN_HISTORY];
+                let s1 = self.history[(i0 + 1) % GRAIN_HISTORY];
+                lerp(s0, s1, position.fract() as f32)
+            }
+        }
+    }
+}
+
+impl Iterator for Granular {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let position = self.position.next()?;
+        let input = self.input.next().unwrap_or(0.0);
+        if self.source == GrainSource::Input {
+            if self.history.is_empty() { self.history = vec![0.0; GRAIN_HISTORY]; }
+            self.history[self.written as usize % GRAIN_HISTORY] = input;
+            self.written += 1;
+        }
+
+        self.countdown -= 1.0;
+        if self.countdown <= 0.0 && self.density > 0.0 {
+            self.countdown += self.sample_rate as f32 / self.density;
+            self.spawn(position);
+        }
+
+        let mut sample = 0.0;
+        for grain in self.grains.iter() {
+            let window = 0.5 - 0.5 * (2.0 * PI * grain.age as f32 / grain.length as f32).cos();
+            sample += window * self.read(grain.position);
+        }
+        for grain in self.grains.iter_mut() {
+            grain.position += grain.step;
+            grain.age += 1;
+        }
+        self.grains.retain(|grain| grain.age < grain.length);
+
+        let overlap = self.density * self.grain_size / 1000.0;
+        Some(sample / overlap.max(1.0).sqrt())
+    }
+}
+
+impl Source for Granular {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1 // Mono sound
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+// Glides towards a target value in about `SMOOTHING_TIME` seconds, so that
+// jumps in a modulated parameter don't click.
+const SMOOTHING_TIME: f32 = 0.005;
+
+#[derive(Debug, Clone, Default)]
+struct Smoother {
+    value: f32,
+    ready: bool,
+}
+
+impl Smoother {
+    fn next(&mut self, target: f32, sample_rate: u32) -> f32 {
+        self.glide(target, SMOOTHING_TIME, sample_rate)
+    }
+
+    fn glide(&mut self, target: f32, seconds: f32, sample_rate: u32) -> f32 {
+        if self.ready {
+            self.value += (target - self.value) / (seconds * sample_rate as f32).max(1.0);
+        } else {
+            self.value = target;
+            self.ready = true;
+        }
+        self.value
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterMode {
+    LowPass,
+    HighPass,
+    BandPass,
+    Notch,
+    Peaking,
+    LowShelf,
+    HighShelf,
+}
+
+impl FilterMode {
+    pub const NAMES: [&'static str; 7] = ["Low Pass", "High Pass", "Band Pass", "Notch", "Peaking", "Low Shelf", "High Shelf"];
+
+    pub fn from_index(index: usize) -> Self {
+        match index {
+            1 => Self::HighPass,
+            2 => Self::BandPass,
+            3 => Self::Notch,
+            4 => Self::Peaking,
+            5 => Self::LowShelf,
+            6 => Self::HighShelf,
+            _ => Self::LowPass,
+        }
+    }
+}
+
+// Normalized biquad coefficients, following the RBJ audio EQ cookbook.
+#[derive(Debug, Clone, Default)]
+struct BiquadCoefficients {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl BiquadCoefficients {
+    fn new(mode: FilterMode, cutoff: f32, q: f32, gain: f32, sample_rate: u32) -> Self {
+        let w0 = 2.0 * PI * cutoff / sample_rate as f32;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * q);
+        let a = 10f32.powf(gain / 40.0);
+        let shelf = 2.0 * a.sqrt() * alpha;
+
+        let (b0, b1, b2, a0, a1, a2) = match mode {
+            FilterMode::LowPass => ((1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            FilterMode::HighPass => ((1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            FilterMode::BandPass => (alpha, 0.0, -alpha, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            FilterMode::Notch => (1.0, -2.0 * cos, 1.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            FilterMode::Peaking => (1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a, 1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a),
+            FilterMode::LowShelf => (
+                a * ((a + 1.0) - (a - 1.0) * cos + shelf),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - shelf),
+                (a + 1.0) + (a - 1.0) * cos + shelf,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - shelf,
+            ),
+            FilterMode::HighShelf => (
+                a * ((a + 1.0) + (a - 1.0) * cos + shelf),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - shelf),
+                (a + 1.0) - (a - 1.0) * cos + shelf,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - shelf,
+            ),
+        };
+
+        Self { b0: b0 / a0, b1: b1 / a0, b2: b2 / a0, a1: a1 / a0, a2: a2 / a0 }
+    }
+}
+
+// A second order filter with modulatable cutoff and Q.
+#[derive(Debug, Clone)]
+pub struct Biquad {
+    stream: Input,
+    cutoff: Input,
+    q: Input,
+    gain: f32,
+    mode: FilterMode,
+    sample_rate: u32,
+    cutoff_smoother: Smoother,
+    q_smoother: Smoother,
+    // The smoothed cutoff and Q the current coefficients were computed for.
+    current: (f32, f32),
+    coefficients: BiquadCoefficients,
+    z1: f32,
+    z2: f32,
+}
+
+impl Biquad {
+    pub fn new() -> Self {
+        Self {
+            stream: Input::default(),
+            cutoff: Input::default(),
+            q: Input::default(),
+            gain: 0f32,
+            mode: FilterMode::LowPass,
+            sample_rate: 44100,
+            cutoff_smoother: Smoother::default(),
+            q_smoother: Smoother::default(),
+            current: (0f32, 0f32),
+            coefficients: BiquadCoefficients::default(),
+            z1: 0f32,
+            z2: 0f32,
+        }
+    }
+
+    pub fn set_stream(&mut self, stream: Stream) { self.stream.set(stream); }
+    pub fn set_cutoff(&mut self, cutoff: Stream) { self.cutoff.set(cutoff); }
+    pub fn set_q(&mut self, q: Stream) { self.q.set(q); }
+    // Boost or cut in dB, only used by the peaking and shelving modes.
+    pub fn set_gain(&mut self, v: f32) { self.gain = v; }
+    pub fn set_mode(&mut self, mode: FilterMode) { self.mode = mode; }
+}
+
+impl Iterator for Biquad {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let x = self.stream.next()?;
+        let nyquist = self.sample_rate as f32 / 2.0;
+        let cutoff = self.cutoff_smoother.next(self.cutoff.next()?.clamp(10.0, nyquist * 0.98), self.sample_rate);
+        let q = self.q_smoother.next(self.q.next()?.max(0.05), self.sample_rate);
+
+        // Only recompute the coefficients while the parameters are moving.
+        if (cutoff - self.current.0).abs() > 1e-3 || (q - self.current.1).abs() > 1e-5 {
+            self.current = (cutoff, q);
+            self.coefficients = BiquadCoefficients::new(self.mode, cutoff, q, self.gain, self.sample_rate);
+        }
+
+        let c = &self.coefficients;
+        let y = c.b0 * x + self.z1;
+        self.z1 = c.b1 * x - c.a1 * y + self.z2;
+        self.z2 = c.b2 * x - c.a2 * y;
+        Some(y)
+    }
+}
+
+impl Source for Biquad {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1 // Mono sound
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+// One side of a stereo pair. Stereo nodes have a Left and a Right output,
+// each with a copy of the node set to that side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Left,
+    Right,
+}
+
+impl Channel {
+    pub const ALL: [Self; 2] = [Self::Left, Self::Right];
+    pub const NAMES: [&'static str; 2] = ["Left", "Right"];
+
+    // Constant power gain of this side for a pan position from -1 to 1.
+    fn pan_gain(self, pan: f32) -> f32 {
+        let angle = (pan.clamp(-1.0, 1.0) + 1.0) * PI / 4.0;
+        match self {
+            Self::Left => angle.cos(),
+            Self::Right => angle.sin(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SvfOutput {
+    LowPass,
+    BandPass,
+    HighPass,
+    Notch,
+}
+
+impl SvfOutput {
+    pub const ALL: [Self; 4] = [Self::LowPass, Self::BandPass, Self::HighPass, Self::Notch];
+    pub const NAMES: [&'static str; 4] = ["Low Pass", "Band Pass", "High Pass", "Notch"];
+}
+
+// A state-variable filter (the trapezoidal version by Andrew Simper), which
+// computes all of its responses at once and stays stable under fast
+// modulation. Every output of the node is a copy set to one response.
+#[derive(Debug, Clone)]
+pub struct StateVariableFilter {
+    stream: Input,
+    cutoff: Input,
+    q: Input,
+    output: SvfOutput,
+    sample_rate: u32,
+    cutoff_smoother: Smoother,
+    q_smoother: Smoother,
+    current: (f32, f32),
+    g: f32,
+    k: f32,
+    ic1eq: f32,
+    ic2eq: f32,
+}
+
+impl StateVariableFilter {
+    pub fn new() -> Self {
+        Self {
+            stream: Input::default(),
+            cutoff: Input::default(),
+            q: Input::default(),
+            output: SvfOutput::LowPass,
+            sample_rate: 44100,
+            cutoff_smoother: Smoother::default(),
+            q_smoother: Smoother::default(),
+            current: (0f32, 0f32),
+            g: 0f32,
+            k: 0f32,
+            ic1eq: 0f32,
+            ic2eq: 0f32,
+        }
+    }
+
+    pub fn set_stream(&mut self, stream: Stream) { self.stream.set(stream); }
+    pub fn set_cutoff(&mut self, cutoff: Stream) { self.cutoff.set(cutoff); }
+    pub fn set_q(&mut self, q: Stream) { self.q.set(q); }
+    pub fn set_output(&mut self, output: SvfOutput) { self.output = output; }
+}
+
+impl Iterator for StateVariableFilter {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let x = self.stream.next()?;
+        let nyquist = self.sample_rate as f32 / 2.0;
+        let cutoff = self.cutoff_smoother.next(self.cutoff.next()?.clamp(10.0, nyquist * 0.98), self.sample_rate);
+        let q = self.q_smoother.next(self.q.next()?.max(0.05), self.sample_rate);
+        if (cutoff - self.current.0).abs() > 1e-3 || (q - self.current.1).abs() > 1e-5 {
+            self.current = (cutoff, q);
+            self.g = (PI * cutoff / self.sample_rate as f32).tan();
+            self.k = 1.0 / q;
+        }
+
+        let a1 = 1.0 / (1.0 + self.g * (self.g + self.k));
+        let a2 = self.g * a1;
+        let a3 = self.g * a2;
+        let v3 = x - self.ic2eq;
+        let v1 = a1 * self.ic1eq + a2 * v3;
+        let v2 = self.ic2eq + a2 * self.ic1eq + a3 * v3;
+        self.ic1eq = 2.0 * v1 - self.ic1eq;
+        self.ic2eq = 2.0 * v2 - self.ic2eq;
+
+        let high = x - self.k * v1 - v2;
+        Some(match self.output {
+            SvfOutput::LowPass => v2,
+            SvfOutput::BandPass => v1,
+            SvfOutput::HighPass => high,
+            SvfOutput::Notch => v2 + high,
+        })
+    }
+}
+
+impl Source for StateVariableFilter {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1 // Mono sound
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+// A Moog style transistor ladder: four saturating one-pole low-passes with
+// the output fed back to the input. It self-oscillates at resonances
+// around 1 and above.
+#[derive(Debug, Clone)]
+pub struct LadderFilter {
+    stream: Input,
+    cutoff: Input,
+    resonance: Input,
+    drive: f32,
+    sample_rate: u32,
+    cutoff_smoother: Smoother,
+    current: f32,
+    g: f32,
+    stages: [f32; 4],
+    output: f32,
+}
+
+impl LadderFilter {
+    pub fn new() -> Self {
+        Self {
+            stream: Input::default(),
+            cutoff: Input::default(),
+            resonance: Input::default(),
+            drive: 1f32,
+            sample_rate: 44100,
+            cutoff_smoother: Smoother::default(),
+            current: 0f32,
+            g: 0f32,
+            stages: [0f32; 4],
+            output: 0f32,
+        }
+    }
+
+    pub fn set_stream(&mut self, stream: Stream) { self.stream.set(stream); }
+    pub fn set_cutoff(&mut self, cutoff: Stream) { self.cutoff.set(cutoff); }
+    pub fn set_resonance(&mut self, resonance: Stream) { self.resonance.set(resonance); }
+    // Gain into the saturating input stage.
+    pub fn set_drive(&mut self, v: f32) { self.drive = v; }
+}
+
+impl Iterator for LadderFilter {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let x = self.stream.next()?;
+        let nyquist = self.sample_rate as f32 / 2.0;
+        let cutoff = self.cutoff_smoother.next(self.cutoff.next()?.clamp(10.0, nyquist * 0.9), self.sample_rate);
+        let resonance = self.resonance.next()?.clamp(0.0, 1.5);
+        if (cutoff - self.current).abs() > 1e-3 {
+            self.current = cutoff;
+            let g = (PI * cutoff / self.sample_rate as f32).tan();
+            self.g = g / (1.0 + g);
+        }
+
+        let mut input = (self.drive.max(0.0) * x - 4.0 * resonance * self.output).tanh();
+        for stage in self.stages.iter_mut() {
+            let v = self.g * (input.tanh() - *stage);
+            input = v + *stage;
+            *stage = input + v;
+        }
+        self.output = input;
+        Some(input)
+    }
+}
+
+impl Source for LadderFilter {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1 // Mono sound
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+// A circular buffer of past samples that can be read at fractional delays.
+#[derive(Debug, Clone, Default)]
+struct DelayLine {
+    buffer: Vec<f32>,
+    write: usize,
+}
+
+impl DelayLine {
+    // Buffers are allocated on first use rather than when the node is
+    // created, templates of the node finder never need one.
+    fn reserve(&mut self, len: usize) {
+        if self.buffer.len() < len {
+            self.buffer = vec![0.0; len];
+            self.write = 0;
+        }
+    }
+
+    fn push(&mut self, x: f32) {
+        self.buffer[self.write] = x;
+        self.write = (self.write + 1) % self.buffer.len();
+    }
+
+    fn at(&self, delay: usize) -> f32 {
+        let len = self.buffer.len();
+        self.buffer[(self.write + len - delay % len) % len]
+    }
+
+    // Reads the sample pushed `delay` samples ago, 1 being the latest one,
+    // with cubic interpolation between samples. A non-finite delay reads
+    // the latest sample.
+    fn read(&self, delay: f32) -> f32 {
+        let delay = if delay.is_finite() { delay } else { 1.0 };
+        let delay = delay.clamp(1.0, (self.buffer.len() - 3) as f32);
+        let i = delay as usize;
+        let t = delay.fract();
+        // Nothing is newer than the latest sample, `at(0)` is the oldest
+        let y0 = if i == 1 { self.at(1) } else { self.at(i - 1) };
+        let (y1, y2, y3) = (self.at(i), self.at(i + 1), self.at(i + 2));
+        let c1 = 0.5 * (y2 - y0);
+        let c2 = y0 - 2.5 * y1 + 2.0 * y2 - 0.5 * y3;
+        let c3 = 0.5 * (y3 - y0) + 1.5 * (y1 - y2);
+        ((c3 * t + c2) * t + c1) * t + y1
+    }
+}
+
+// Note lengths a delay can lock to, in beats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TempoSync {
+    Off,
+    Whole,
+    Half,
+    Quarter,
+    Eighth,
+    Sixteenth,
+    DottedQuarter,
+    DottedEighth,
+    TripletQuarter,
+    TripletEighth,
+}
+
+impl TempoSync {
+    pub const NAMES: [&'static str; 10] = ["Off", "1/1", "1/2", "1/4", "1/8", "1/16", "1/4 dotted", "1/8 dotted", "1/4 triplet", "1/8 triplet"];
+
+    pub fn from_index(index: usize) -> Self {
+        match index {
+            1 => Self::Whole,
+            2 => Self::Half,
+            3 => Self::Quarter,
+            4 => Self::Eighth,
+            5 => Self::Sixteenth,
+            6 => Self::DottedQuarter,
+            7 => Self::DottedEighth,
+            8 => Self::TripletQuarter,
+            9 => Self::TripletEighth,
+            _ => Self::Off,
+        }
+    }
+
+    fn beats(self) -> Option<f32> {
+        match self {
+            Self::Off => None,
+            Self::Whole => Some(4.0),
+            Self::Half => Some(2.0),
+            Self::Quarter => Some(1.0),
+            Self::Eighth => Some(0.5),
+            Self::Sixteenth => Some(0.25),
+            Self::DottedQuarter => Some(1.5),
+            Self::DottedEighth => Some(0.75),
+            Self::TripletQuarter => Some(2.0 / 3.0),
+            Self::TripletEighth => Some(1.0 / 3.0),
+        }
+    }
+}
+
+const MAX_DELAY_SECONDS: f32 = 4.0;
+
+// An echo with feedback. The delay time glides to new values, so modulating
+// it bends the pitch of the echoes instead of clicking.
+#[derive(Debug, Clone)]
+pub struct Delay {
+    stream: Input,
+    time: Input,
+    feedback: f32,
+    mix: f32,
+    feedback_cutoff: f32,
+    sync: TempoSync,
+    bpm: f32,
+    ping_pong: bool,
+    channel: Channel,
+    sample_rate: u32,
+    line: DelayLine,
+    right_line: DelayLine,
+    time_smoother: Smoother,
+    low_pass: f32,
+    right_low_pass: f32,
+}
+
+impl Delay {
+    pub fn new() -> Self {
+        Self {
+            stream: Input::default(),
+            time: Input::default(),
+            feedback: 0f32,
+            mix: 0.5,
+            feedback_cutoff: 0f32,
+            sync: TempoSync::Off,
+            bpm: 120f32,
+            ping_pong: false,
+            channel: Channel::Left,
+            sample_rate: 44100,
+            line: DelayLine::default(),
+            right_line: DelayLine::default(),
+            time_smoother: Smoother::default(),
+            low_pass: 0f32,
+            right_low_pass: 0f32,
+        }
+    }
+
+    pub fn set_stream(&mut self, stream: Stream) { self.stream.set(stream); }
+    // Delay time in milliseconds, ignored while synced to the tempo.
+    pub fn set_time(&mut self, time: Stream) { self.time.set(time); }
+    pub fn set_feedback(&mut self, v: f32) { self.feedback = v; }
+    pub fn set_mix(&mut self, v: f32) { self.mix = v; }
+    // Cutoff of the low-pass darkening each repeat, 0 turns it off.
+    pub fn set_feedback_cutoff(&mut self, v: f32) { self.feedback_cutoff = v; }
+    pub fn set_sync(&mut self, sync: TempoSync) { self.sync = sync; }
+    pub fn set_bpm(&mut self, v: f32) { self.bpm = v; }
+    // Bounces the echoes between the sides instead of repeating in the middle.
+    pub fn set_ping_pong(&mut self, ping_pong: bool) { self.ping_pong = ping_pong; }
+    pub fn set_channel(&mut self, channel: Channel) { self.channel = channel; }
+}
+
+// Darkens the feedback of a delay with a one-pole low-pass, a cutoff of 0
+// leaves it untouched.
+fn feedback_filter(state: &mut f32, x: f32, cutoff: f32, sample_rate: f32) -> f32 {
+    if cutoff <= 0.0 {
+        return x;
+    }
+    let a = 1.0 - (-2.0 * PI * cutoff / sample_rate).exp();
+    *state += a * (x - *state);
+    *state
+}
+
+impl Iterator for Delay {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let x = self.stream.next()?;
+        let time = self.time.next()?;
+        let sample_rate = self.sample_rate as f32;
+        self.line.reserve((MAX_DELAY_SECONDS * sample_rate) as usize + 4);
+
+        let seconds = match self.sync.beats() {
+            Some(beats) => beats * 60.0 / self.bpm.max(1.0),
+            None => time / 1000.0,
+        };
+        let delay = self.time_smoother.glide(seconds.clamp(0.0, MAX_DELAY_SECONDS) * sample_rate, 0.05, self.sample_rate);
+        let delayed = self.line.read(delay);
+        let amount = self.feedback.clamp(-0.99, 0.99);
+        let mix = self.mix.clamp(0.0, 1.0);
+
+        if !self.ping_pong {
+            let feedback = feedback_filter(&mut self.low_pass, delayed * amount, self.feedback_cutoff, sample_rate);
+            self.line.push(x + feedback);
+            return Some(lerp(x, delayed, mix));
+        }
+
+        // The input only enters on the left, each side feeds the other.
+        self.right_line.reserve((MAX_DELAY_SECONDS * sample_rate) as usize + 4);
+        let right_delayed = self.right_line.read(delay);
+        let left_feedback = feedback_filter(&mut self.low_pass, right_delayed * amount, self.feedback_cutoff, sample_rate);
+        let right_feedback = feedback_filter(&mut self.right_low_pass, delayed * amount, self.feedback_cutoff, sample_rate);
+        self.line.push(x + left_feedback);
+        self.right_line.push(right_feedback);
+
+        Some(match self.channel {
+            Channel::Left => lerp(x, delayed, mix),
+            Channel::Right => lerp(x, right_delayed, mix),
+        })
+    }
+}
+
+impl Source for Delay {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1 // Mono sound
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+// Comb and all-pass lengths of Freeverb, tuned for 44.1kHz.
+const COMB_TUNINGS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASS_TUNINGS: [usize; 4] = [556, 441, 341, 225];
+const MAX_PRE_DELAY_SECONDS: f32 = 0.5;
+// Extra length of the right side's delays, decorrelates the two sides.
+const STEREO_SPREAD: usize = 23;
+
+// A feedback comb filter with a one-pole low-pass in the loop.
+#[derive(Debug, Clone)]
+struct Comb {
+    buffer: Vec<f32>,
+    index: usize,
+    store: f32,
+}
+
+impl Comb {
+    fn new(len: usize) -> Self {
+        Self { buffer: vec![0.0; len.max(1)], index: 0, store: 0.0 }
+    }
+
+    fn process(&mut self, x: f32, feedback: f32, damping: f32) -> f32 {
+        let y = self.buffer[self.index];
+        self.store = y * (1.0 - damping) + self.store * damping;
+        self.buffer[self.index] = x + self.store * feedback;
+        self.index = (self.index + 1) % self.buffer.len();
+        y
+    }
+}
+
+// A Schroeder all-pass used to diffuse the combs' output.
+#[derive(Debug, Clone)]
+struct Allpass {
+    buffer: Vec<f32>,
+    index: usize,
+}
+
+impl Allpass {
+    fn new(len: usize) -> Self {
+        Self { buffer: vec![0.0; len.max(1)], index: 0 }
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        let delayed = self.buffer[self.index];
+        self.buffer[self.index] = x + delayed * 0.5;
+        self.index = (self.index + 1) % self.buffer.len();
+        delayed - x
+    }
+}
+
+// An algorithmic reverb after Jezar's Freeverb: parallel damped combs
+// followed by all-passes in series, behind a pre-delay.
+#[derive(Debug, Clone)]
+pub struct Reverb {
+    stream: Input,
+    room_size: f32,
+    damping: f32,
+    pre_delay: f32,
+    mix: f32,
+    channel: Channel,
+    sample_rate: u32,
+    pre_delay_line: DelayLine,
+    combs: Vec<Comb>,
+    allpasses: Vec<Allpass>,
+}
+
+impl Reverb {
+    pub fn new() -> Self {
+        Self {
+            stream: Input::default(),
+            room_size: 0.5,
+            damping: 0.5,
+            pre_delay: 0f32,
+            mix: 0.3,
+            channel: Channel::Left,
+            sample_rate: 44100,
+            pre_delay_line: DelayLine::default(),
+            combs: Vec::new(),
+            allpasses: Vec::new(),
+        }
+    }
+
+    pub fn set_stream(&mut self, stream: Stream) { self.stream.set(stream); }
+    pub fn set_room_size(&mut self, v: f32) { self.room_size = v; }
+    pub fn set_damping(&mut self, v: f32) { self.damping = v; }
+    // Pre-delay in milliseconds.
+    pub fn set_pre_delay(&mut self, v: f32) { self.pre_delay = v; }
+    pub fn set_mix(&mut self, v: f32) { self.mix = v; }
+    pub fn set_channel(&mut self, channel: Channel) { self.channel = channel; }
+
+    fn prepare(&mut self) {
+        let scale = self.sample_rate as f32 / 44100.0;
+        let spread = if self.channel == Channel::Right { STEREO_SPREAD } else { 0 };
+        let scaled = |len: usize| ((len + spread) as f32 * scale) as usize;
+        self.combs = COMB_TUNINGS.iter().map(|&len| Comb::new(scaled(len))).collect();
+        self.allpasses = ALLPASS_TUNINGS.iter().map(|&len| Allpass::new(scaled(len))).collect();
+        self.pre_delay_line.reserve((MAX_PRE_DELAY_SECONDS * self.sample_rate as f32) as usize + 4);
+    }
+}
+
+impl Iterator for Reverb {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let x = self.stream.next()?;
+        if self.combs.is_empty() {
+            self.prepare();
+        }
+
+        let pre_delay = (self.pre_delay / 1000.0).clamp(0.0, MAX_PRE_DELAY_SECONDS) * self.sample_rate as f32;
+        self.pre_delay_line.push(x);
+        let input = self.pre_delay_line.read(pre_delay + 1.0) * 0.015;
+
+        let feedback = self.room_size.clamp(0.0, 1.0) * 0.28 + 0.7;
+        let damping = self.damping.clamp(0.0, 1.0) * 0.4;
+        let mut wet = self.combs.iter_mut().map(|comb| comb.process(input, feedback, damping)).sum::<f32>();
+        for allpass in self.allpasses.iter_mut() {
+            wet = allpass.process(wet);
+        }
+
+        // Freeverb's wet gain of 3 brings the tail back up to the dry level.
+        let mix = self.mix.clamp(0.0, 1.0);
+        Some(x * (1.0 - mix) + wet * 3.0 * mix)
+    }
+}
+
+impl Source for Reverb {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1 // Mono sound
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+// A sine LFO for the modulation effects, replaced by the external stream
+// when one is connected. Both run from -1 to 1.
+#[derive(Debug, Clone)]
+struct Lfo {
+    external: Input,
+    phase: f32,
+    channel: Channel,
+}
+
+impl Lfo {
+    fn new() -> Self {
+        Self { external: Input::default(), phase: 0f32, channel: Channel::Left }
+    }
+
+    // The right side runs a quarter cycle ahead, or inverted when external.
+    fn next(&mut self, rate: f32, sample_rate: u32) -> f32 {
+        let right = self.channel == Channel::Right;
+        let internal = sine_wave(self.phase + if right { 0.25 } else { 0.0 });
+        self.phase = (self.phase + rate / sample_rate as f32).rem_euclid(1.0);
+        match self.external.next() {
+            Some(external) if right => -external,
+            Some(external) => external,
+            None => internal,
+        }
+    }
+}
+
+// A delay line swept by an LFO, shared by the chorus and the flanger.
+#[derive(Debug, Clone)]
+struct ModulatedDelay {
+    lfo: Lfo,
+    line: DelayLine,
+    last: f32,
+}
+
+impl ModulatedDelay {
+    fn new() -> Self {
+        Self { lfo: Lfo::new(), line: DelayLine::default(), last: 0f32 }
+    }
+
+    // Delays `x` by `center` plus or minus `width` milliseconds and feeds
+    // the delayed signal back in.
+    fn process(&mut self, x: f32, center: f32, width: f32, rate: f32, feedback: f32, sample_rate: u32) -> f32 {
+        let samples_per_ms = sample_rate as f32 / 1000.0;
+        self.line.reserve(((center + width) * samples_per_ms) as usize + 4);
+        let delay = (center + width * self.lfo.next(rate, sample_rate)) * samples_per_ms;
+        self.line.push(x + self.last * feedback.clamp(-0.95, 0.95));
+        self.last = self.line.read(delay + 1.0);
+        self.last
+    }
+}
+
+// Thickens a sound with a slowly swept delay of a few tens of milliseconds.
+#[derive(Debug, Clone)]
+pub struct Chorus {
+    stream: Input,
+    rate: f32,
+    depth: f32,
+    feedback: f32,
+    mix: f32,
+    sample_rate: u32,
+    delay: ModulatedDelay,
+}
+
+impl Chorus {
+    pub fn new() -> Self {
+        Self {
+            stream: Input::default(),
+            rate: 0f32,
+            depth: 0f32,
+            feedback: 0f32,
+            mix: 0.5,
+            sample_rate: 44100,
+            delay: ModulatedDelay::new(),
+        }
+    }
+
+    pub fn set_stream(&mut self, stream: Stream) { self.stream.set(stream); }
+    pub fn set_lfo(&mut self, stream: Stream) { self.delay.lfo.external.set(stream); }
+    pub fn set_channel(&mut self, channel: Channel) { self.delay.lfo.channel = channel; }
+    pub fn set_rate(&mut self, v: f32) { self.rate = v; }
+    pub fn set_depth(&mut self, v: f32) { self.depth = v; }
+    pub fn set_feedback(&mut self, v: f32) { self.feedback = v; }
+    pub fn set_mix(&mut self, v: f32) { self.mix = v; }
+}
+
+impl Iterator for Chorus {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let x = self.stream.next()?;
+        let width = self.depth.clamp(0.0, 1.0) * 10.0;
+        let wet = self.delay.process(x, 20.0, width, self.rate, self.feedback, self.sample_rate);
+        Some(lerp(x, wet, self.mix.clamp(0.0, 1.0)))
+    }
+}
+
+impl Source for Chorus {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1 // Mono sound
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+// A comb filter swept by a very short delay, feedback sharpens the notches.
+#[derive(Debug, Clone)]
+pub struct Flanger {
+    stream: Input,
+    rate: f32,
+    depth: f32,
+    feedback: f32,
+    mix: f32,
+    sample_rate: u32,
+    delay: ModulatedDelay,
+}
+
+impl Flanger {
+    pub fn new() -> Self {
+        Self {
+            stream: Input::default(),
+            rate: 0f32,
+            depth: 0f32,
+            feedback: 0f32,
+            mix: 0.5,
+            sample_rate: 44100,
+            delay: ModulatedDelay::new(),
+        }
+    }
+
+    pub fn set_stream(&mut self, stream: Stream) { self.stream.set(stream); }
+    pub fn set_lfo(&mut self, stream: Stream) { self.delay.lfo.external.set(stream); }
+    pub fn set_channel(&mut self, channel: Channel) { self.delay.lfo.channel = channel; }
+    pub fn set_rate(&mut self, v: f32) { self.rate = v; }
+    pub fn set_depth(&mut self, v: f32) { self.depth = v; }
+    pub fn set_feedback(&mut self, v: f32) { self.feedback = v; }
+    pub fn set_mix(&mut self, v: f32) { self.mix = v; }
+}
+
+impl Iterator for Flanger {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let x = self.stream.next()?;
+        // Sweeps between 0.1ms and up to 10ms.
+        let width = self.depth.clamp(0.0, 1.0) * 4.95;
+        let wet = self.delay.process(x, 0.1 + width, width, self.rate, self.feedback, self.sample_rate);
+        Some(lerp(x, wet, self.mix.clamp(0.0, 1.0)))
+    }
+}
+
+impl Source for Flanger {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1 // Mono sound
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+const PHASER_STAGES: usize = 6;
+
+// Sweeps notches through the spectrum with a chain of first order all-pass
+// filters, no delay line involved.
+#[derive(Debug, Clone)]
+pub struct Phaser {
+    stream: Input,
+    rate: f32,
+    depth: f32,
+    feedback: f32,
+    mix: f32,
+    sample_rate: u32,
+    lfo: Lfo,
+    stages: [f32; PHASER_STAGES],
+    last: f32,
+}
+
+impl Phaser {
+    pub fn new() -> Self {
+        Self {
+            stream: Input::default(),
+            rate: 0f32,
+            depth: 0f32,
+            feedback: 0f32,
+            mix: 0.5,
+            sample_rate: 44100,
+            lfo: Lfo::new(),
+            stages: [0f32; PHASER_STAGES],
+            last: 0f32,
+        }
+    }
+
+    pub fn set_stream(&mut self, stream: Stream) { self.stream.set(stream); }
+    pub fn set_lfo(&mut self, stream: Stream) { self.lfo.external.set(stream); }
+    pub fn set_channel(&mut self, channel: Channel) { self.lfo.channel = channel; }
+    pub fn set_rate(&mut self, v: f32) { self.rate = v; }
+    pub fn set_depth(&mut self, v: f32) { self.depth = v; }
+    pub fn set_feedback(&mut self, v: f32) { self.feedback = v; }
+    pub fn set_mix(&mut self, v: f32) { self.mix = v; }
+}
+
+impl Iterator for Phaser {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let x = self.stream.next()?;
+        let sample_rate = self.sample_rate as f32;
+
+        // The break frequency moves exponentially around 800Hz, up to
+        // three octaves each way at full depth.
+        let lfo = self.lfo.next(self.rate, self.sample_rate);
+        let frequency = (800.0 * (self.depth.clamp(0.0, 1.0) * 3.0 * lfo).exp2()).min(sample_rate * 0.45);
+        let t = (PI * frequency / sample_rate).tan();
+        let a = (t - 1.0) / (t + 1.0);
+
+        let mut y = x + self.last * self.feedback.clamp(-0.95, 0.95);
+        for state in self.stages.iter_mut() {
+            let v = y - a * *state;
+            y = a * v + *state;
+            *state = v;
+        }
+        self.last = y;
+
+        Some(lerp(x, y, self.mix.clamp(0.0, 1.0)))
+    }
+}
+
+impl Source for Phaser {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1 // Mono sound
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+// Number of evenly spaced points of a drawn shaper curve, spanning -1 to 1.
+pub const CURVE_POINTS: usize = 17;
+
+pub fn identity_curve() -> Vec<f32> {
+    (0..CURVE_POINTS).map(|i| i as f32 / (CURVE_POINTS - 1) as f32 * 2.0 - 1.0).collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShaperCurve {
+    Tanh,
+    HardClip,
+    Foldback,
+    Chebyshev,
+    Drawn,
+}
+
+impl ShaperCurve {
+    pub const NAMES: [&'static str; 5] = ["Tanh", "Hard Clip", "Foldback", "Chebyshev", "Drawn"];
+
+    pub fn from_index(index: usize) -> Self {
+        match index {
+            1 => Self::HardClip,
+            2 => Self::Foldback,
+            3 => Self::Chebyshev,
+            4 => Self::Drawn,
+            _ => Self::Tanh,
+        }
+    }
+}
+
+// Chebyshev polynomial of the first kind, turns a full scale sine into
+// exactly its `order`th harmonic.
+fn chebyshev(order: u32, x: f32) -> f32 {
+    let x = x.clamp(-1.0, 1.0);
+    let (mut previous, mut current) = (1.0, x);
+    if order == 0 {
+        return previous;
+    }
+    for _ in 1..order {
+        let next = 2.0 * x * current - previous;
+        previous = current;
+        current = next;
+    }
+    current
+}
+
+fn drawn_curve(points: &[f32], x: f32) -> f32 {
+    if points.len() < 2 {
+        return x;
+    }
+    let position = (x.clamp(-1.0, 1.0) + 1.0) / 2.0 * (points.len() - 1) as f32;
+    let i = (position as usize).min(points.len() - 2);
+    lerp(points[i], points[i + 1], position - i as f32)
+}
+
+// Runs a function at a multiple of the sample rate, with windowed sinc
+// filters on the way up and down to keep the harmonics it creates from
+// folding back.
+#[derive(Debug, Clone)]
+struct Oversampler {
+    factor: usize,
+    taps: Vec<f32>,
+    up: Vec<f32>,
+    down: Vec<f32>,
+}
+
+impl Oversampler {
+    fn new(factor: usize) -> Self {
+        let factor = factor.max(1);
+        if factor == 1 {
+            return Self { factor, taps: vec![], up: vec![], down: vec![] };
+        }
+
+        let len = 16 * factor + 1;
+        let middle = (len / 2) as f32;
+        let cutoff = 0.45 / factor as f32;
+        let mut taps: Vec<f32> = (0..len).map(|k| {
+            let t = k as f32 - middle;
+            let sinc = if t == 0.0 { 1.0 } else { (2.0 * PI * cutoff * t).sin() / (2.0 * PI * cutoff * t) };
+            let window = 0.42 - 0.5 * (2.0 * PI * k as f32 / (len - 1) as f32).cos() + 0.08 * (4.0 * PI * k as f32 / (len - 1) as f32).cos();
+            sinc * window
+        }).collect();
+        let sum: f32 = taps.iter().sum();
+        taps.iter_mut().for_each(|tap| *tap /= sum);
+
+        Self { factor, taps, up: vec![0.0; len / factor + 1], down: vec![0.0; len] }
+    }
+
+    fn process(&mut self, x: f32, mut f: impl FnMut(f32) -> f32) -> f32 {
+        if self.factor == 1 {
+            return f(x);
+        }
+
+        let up_len = self.up.len();
+        self.up.copy_within(0..up_len - 1, 1);
+        self.up[0] = x;
+
+        // Only every `factor`th tap lines up with a real sample, the rest
+        // would multiply the stuffed zeros.
+        let down_len = self.down.len();
+        for phase in 0..self.factor {
+            let y: f32 = self.taps.iter().skip(phase).step_by(self.factor).zip(self.up.iter()).map(|(tap, x)| tap * x).sum();
+            self.down.copy_within(0..down_len - 1, 1);
+            self.down[0] = f(y * self.factor as f32);
+        }
+        self.taps.iter().zip(self.down.iter()).map(|(tap, x)| tap * x).sum()
+    }
+}
+
+// A waveshaper, drives the input into one of a few transfer functions.
+#[derive(Debug, Clone)]
+pub struct Distortion {
+    stream: Input,
+    drive: Input,
+    curve: ShaperCurve,
+    order: u32,
+    points: Vec<f32>,
+    sample_rate: u32,
+    oversampler: Oversampler,
+}
+
+impl Distortion {
+    pub fn new() -> Self {
+        Self {
+            stream: Input::default(),
+            drive: Input::default(),
+            curve: ShaperCurve::Tanh,
+            order: 1,
+            points: identity_curve(),
+            sample_rate: 44100,
+            oversampler: Oversampler::new(1),
+        }
+    }
+
+    pub fn set_stream(&mut self, stream: Stream) { self.stream.set(stream); }
+    pub fn set_drive(&mut self, stream: Stream) { self.drive.set(stream); }
+    pub fn set_curve(&mut self, curve: ShaperCurve) { self.curve = curve; }
+    pub fn set_order(&mut self, v: f32) { self.order = v.clamp(0.0, 32.0) as u32; }
+    pub fn set_points(&mut self, points: Vec<f32>) { self.points = points; }
+    pub fn set_oversampling(&mut self, factor: usize) { self.oversampler = Oversampler::new(factor); }
+}
+
+impl Iterator for Distortion {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let x = self.stream.next()? * self.drive.next()?;
+        let (curve, order, points) = (self.curve, self.order, &self.points);
+        Some(self.oversampler.process(x, |x| match curve {
+            ShaperCurve::Tanh => x.tanh(),
+            ShaperCurve::HardClip => x.clamp(-1.0, 1.0),
+            ShaperCurve::Foldback => 1.0 - ((x + 1.0).rem_euclid(4.0) - 2.0).abs(),
+            ShaperCurve::Chebyshev => chebyshev(order, x),
+            ShaperCurve::Drawn => drawn_curve(points, x),
+        }))
+    }
+}
+
+impl Source for Distortion {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1 // Mono sound
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+// Lo-fi reduction of both the bit depth and the sample rate.
+#[derive(Debug, Clone)]
+pub struct Bitcrusher {
+    stream: Input,
+    bits: f32,
+    rate: Input,
+    dither: bool,
+    sample_rate: u32,
+    rng: Rng,
+    phase: f32,
+    held: f32,
+}
+
+impl Bitcrusher {
+    pub fn new() -> Self {
+        Self {
+            stream: Input::default(),
+            bits: 8f32,
+            rate: Input::default(),
+            dither: false,
+            sample_rate: 44100,
+            rng: Rng::new(),
+            phase: 1f32,
+            held: 0f32,
+        }
+    }
+
+    pub fn set_stream(&mut self, stream: Stream) { self.stream.set(stream); }
+    // Fractional depths are allowed, to sweep smoothly between them.
+    pub fn set_bits(&mut self, v: f32) { self.bits = v; }
+    // Rate of the sample and hold in Hz.
+    pub fn set_rate(&mut self, stream: Stream) { self.rate.set(stream); }
+    pub fn set_dither(&mut self, dither: bool) { self.dither = dither; }
+}
+
+impl Iterator for Bitcrusher {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let x = self.stream.next()?;
+        let rate = self.rate.next()?;
+
+        // A new sample is taken every time the phase wraps, it starts
+        // wrapped so the first one is never silence.
+        if self.phase >= 1.0 {
+            self.phase = self.phase.fract();
+            let levels = (self.bits.clamp(1.0, 24.0) - 1.0).exp2();
+            // Triangular dither of one step, decorrelates the rounding error
+            // from the signal at the cost of a little noise.
+            let dither = if self.dither { self.rng.next_f32() - self.rng.next_f32() } else { 0.0 };
+            // Like a converter, one step short of +1 so there are 2^bits levels.
+            self.held = ((x * levels + dither).round() / levels).clamp(-1.0, 1.0 - 1.0 / levels);
+        }
+        self.phase += rate.max(0.0) / self.sample_rate as f32;
+
+        Some(self.held)
+    }
+}
+
+impl Source for Bitcrusher {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1 // Mono sound
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+fn db_to_gain(db: f32) -> f32 { 10f32.powf(db / 20.0) }
+fn gain_to_db(gain: f32) -> f32 { 20.0 * gain.max(1e-6).log10() }
+
+// One-pole coefficient reaching ~63% of a step after `ms` milliseconds.
+fn time_coefficient(ms: f32, sample_rate: u32) -> f32 {
+    (-1.0 / (ms.max(0.01) / 1000.0 * sample_rate as f32)).exp()
+}
+
+// A feed-forward compressor with a soft knee. The level is detected on the
+// sidechain when connected, otherwise on the input itself.
+#[derive(Debug, Clone)]
+pub struct Compressor {
+    stream: Input,
+    sidechain: Input,
+    threshold: f32,
+    ratio: f32,
+    attack: f32,
+    release: f32,
+    knee: f32,
+    makeup: f32,
+    sample_rate: u32,
+    reduction: f32,
+}
+
+impl Compressor {
+    pub fn new() -> Self {
+        Self {
+            stream: Input::default(),
+            sidechain: Input::default(),
+            threshold: 0f32,
+            ratio: 1f32,
+            attack: 10f32,
+            release: 100f32,
+            knee: 0f32,
+            makeup: 0f32,
+            sample_rate: 44100,
+            reduction: 0f32,
+        }
+    }
+
+    pub fn set_stream(&mut self, stream: Stream) { self.stream.set(stream); }
+    pub fn set_sidechain(&mut self, stream: Stream) { self.sidechain.set(stream); }
+    // Threshold, knee width and makeup gain are in dB, times in milliseconds.
+    pub fn set_threshold(&mut self, v: f32) { self.threshold = v; }
+    pub fn set_ratio(&mut self, v: f32) { self.ratio = v; }
+    pub fn set_attack(&mut self, v: f32) { self.attack = v; }
+    pub fn set_release(&mut self, v: f32) { self.release = v; }
+    pub fn set_knee(&mut self, v: f32) { self.knee = v; }
+    pub fn set_makeup(&mut self, v: f32) { self.makeup = v; }
+
+    // The static curve, output level in dB for an input level in dB.
+    fn curve(&self, level: f32) -> f32 {
+        let (threshold, knee) = (self.threshold, self.knee.max(0.0));
+        let slope = 1.0 / self.ratio.max(1.0) - 1.0;
+        let over = level - threshold;
+        if 2.0 * over < -knee {
+            level
+        } else if knee > 0.0 && 2.0 * over.abs() <= knee {
+            level + slope * (over + knee / 2.0).powi(2) / (2.0 * knee)
+        } else {
+            level + slope * over
+        }
+    }
+}
+
+impl Iterator for Compressor {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let x = self.stream.next()?;
+        let detected = self.sidechain.next().unwrap_or(x);
+
+        let level = gain_to_db(detected.abs());
+        let target = level - self.curve(level);
+        let ms = if target > self.reduction { self.attack } else { self.release };
+        let a = time_coefficient(ms, self.sample_rate);
+        self.reduction = a * self.reduction + (1.0 - a) * target;
+
+        Some(x * db_to_gain(self.makeup - self.reduction))
+    }
+}
+
+impl Source for Compressor {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1 // Mono sound
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+// A lookahead brickwall limiter, the output never goes over the threshold.
+//
+// The input is delayed by the lookahead while the gain it will need is worked
+// out: the lowest gain required within the window, released slowly, then
+// averaged over the window again so it ramps down in time for every peak.
+#[derive(Debug, Clone)]
+pub struct Limiter {
+    stream: Input,
+    sidechain: Input,
+    threshold: f32,
+    lookahead: f32,
+    release: f32,
+    makeup: f32,
+    sample_rate: u32,
+    line: DelayLine,
+    window: VecDeque<(usize, f32)>,
+    released: f32,
+    ramp: Vec<f32>,
+    ramp_sum: f64,
+    position: usize,
+}
+
+impl Limiter {
+    pub fn new() -> Self {
+        Self {
+            stream: Input::default(),
+            sidechain: Input::default(),
+            threshold: 0f32,
+            lookahead: 5f32,
+            release: 100f32,
+            makeup: 0f32,
+            sample_rate: 44100,
+            line: DelayLine::default(),
+            window: VecDeque::new(),
+            released: 1f32,
+            ramp: vec![],
+            ramp_sum: 0f64,
+            position: 0,
+        }
+    }
+
+    pub fn set_stream(&mut self, stream: Stream) { self.stream.set(stream); }
+    pub fn set_sidechain(&mut self, stream: Stream) { self.sidechain.set(stream); }
+    // Threshold and makeup gain are in dB, times in milliseconds.
+    pub fn set_threshold(&mut self, v: f32) { self.threshold = v; }
+    pub fn set_lookahead(&mut self, v: f32) { self.lookahead = v; }
+    pub fn set_release(&mut self, v: f32) { self.release = v; }
+    pub fn set_makeup(&mut self, v: f32) { self.makeup = v; }
+
+    fn prepare(&mut self, len: usize) {
+        self.line = DelayLine::default();
+        self.line.reserve(len + 4);
+        self.window.clear();
+        self.window.reserve(len + 2);
+        self.released = 1.0;
+        self.ramp = vec![1.0; len];
+        self.ramp_sum = len as f64;
+    }
+}
+
+impl Iterator for Limiter {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let makeup = db_to_gain(self.makeup);
+        let x = self.stream.next()? * makeup;
+        let detected = self.sidechain.next().map_or(x, |s| s * makeup);
+        Some(self.process(x, detected))
+    }
+}
+
+impl Limiter {
+    fn process(&mut self, x: f32, detected: f32) -> f32 {
+        let gain = self.next_gain(detected);
+        self.line.push(x);
+        let delayed = self.line.at(self.latency() + 1);
+        let ceiling = db_to_gain(self.threshold);
+        (delayed * gain).clamp(-ceiling, ceiling)
+    }
+
+    // How many samples late the gain from `next_gain` applies.
+    fn latency(&self) -> usize {
+        self.ramp.len()
+    }
+
+    fn window_len(&self) -> usize {
+        ((self.lookahead.clamp(0.0, 50.0) / 1000.0 * self.sample_rate as f32) as usize).max(1)
+    }
+
+    fn next_gain(&mut self, detected: f32) -> f32 {
+        let len = self.window_len();
+        if self.ramp.len() != len {
+            self.prepare(len);
+        }
+
+        let ceiling = db_to_gain(self.threshold);
+        let required = if detected.abs() > ceiling { ceiling / detected.abs() } else { 1.0 };
+
+        // Minimum over the last `len + 1` samples, kept in increasing order
+        // so the front is always the minimum.
+        while self.window.back().map_or(false, |&(_, g)| g >= required) {
+            self.window.pop_back();
+        }
+        self.window.push_back((self.position, required));
+        while self.window.front().map_or(false, |&(i, _)| i + len < self.position) {
+            self.window.pop_front();
+        }
+        let minimum = self.window.front().map_or(1.0, |&(_, g)| g);
+
+        self.released = if minimum < self.released {
+            minimum
+        } else {
+            let a = time_coefficient(self.release, self.sample_rate);
+            a * self.released + (1.0 - a) * minimum
+        };
+
+        let slot = self.position % len;
+        self.ramp_sum += (self.released - self.ramp[slot]) as f64;
+        self.ramp[slot] = self.released;
+        self.position += 1;
+        (self.ramp_sum / len as f64) as f32
+    }
+}
+
+impl Source for Limiter {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1 // Mono sound
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+// Places a mono stream in the stereo field, keeping its power constant.
+#[derive(Debug, Clone)]
+pub struct Pan {
+    stream: Input,
+    pan: Input,
+    channel: Channel,
+    sample_rate: u32,
+}
+
+impl Pan {
+    pub fn new() -> Self {
+        Self {
+            stream: Input::default(),
+            pan: Input::default(),
+            channel: Channel::Left,
+            sample_rate: 44100,
+        }
+    }
+
+    pub fn set_stream(&mut self, stream: Stream) { self.stream.set(stream); }
+    // From -1 (left) to 1 (right).
+    pub fn set_pan(&mut self, stream: Stream) { self.pan.set(stream); }
+    pub fn set_channel(&mut self, channel: Channel) { self.channel = channel; }
+}
+
+impl Iterator for Pan {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let x = self.stream.next()?;
+        let pan = self.pan.next()?;
+        Some(x * self.channel.pan_gain(pan))
+    }
+}
+
+impl Source for Pan {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1 // One side of a stereo pair
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+// Scales the difference between the sides of a stereo pair, 0 folds it to
+// mono, 1 leaves it as is and above 1 widens it.
+#[derive(Debug, Clone)]
+pub struct Width {
+    left: Input,
+    right: Input,
+    width: f32,
+    channel: Channel,
+    sample_rate: u32,
+}
+
+impl Width {
+    pub fn new() -> Self {
+        Self {
+            left: Input::default(),
+            right: Input::default(),
+            width: 1f32,
+            channel: Channel::Left,
+            sample_rate: 44100,
+        }
+    }
+
+    pub fn set_left(&mut self, stream: Stream) { self.left.set(stream); }
+    pub fn set_right(&mut self, stream: Stream) { self.right.set(stream); }
+    pub fn set_width(&mut self, v: f32) { self.width = v; }
+    pub fn set_channel(&mut self, channel: Channel) { self.channel = channel; }
+}
+
+impl Iterator for Width {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let left = self.left.next()?;
+        let right = self.right.next()?;
+        let mid = (left + right) / 2.0;
+        let side = (left - right) / 2.0 * self.width.max(0.0);
+        Some(match self.channel {
+            Channel::Left => mid + side,
+            Channel::Right => mid - side,
+        })
+    }
+}
+
+impl Source for Width {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1 // One side of a stereo pair
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+// A named end point of the graph. Several of them can be played together
+// and are exported to a file each. Passes its stereo pair through, a missing
+// right side copies the left one.
+#[derive(Debug, Clone)]
+pub struct Output {
+    name: String,
+    left: Input,
+    right: Input,
+    channel: Channel,
+    sample_rate: u32,
+}
+
+impl Output {
+    pub fn new() -> Self {
+        Self {
+            name: String::new(),
+            left: Input::default(),
+            right: Input::default(),
+            channel: Channel::Left,
+            sample_rate: 44100,
+        }
+    }
+
+    pub fn set_name(&mut self, name: String) { self.name = name; }
+    pub fn set_left(&mut self, stream: Stream) { self.left.set(stream); }
+    pub fn set_right(&mut self, stream: Stream) { self.right.set(stream); }
+    pub fn set_channel(&mut self, channel: Channel) { self.channel = channel; }
+
+    pub fn name(&self) -> &str { &self.name }
+
+    // Splits the output into a stream for each side.
+    pub fn sides(self) -> (Stream, Stream) {
+        let mut right = self.clone();
+        right.set_channel(Channel::Right);
+        (Stream::Output(self), Stream::Output(right))
+    }
+}
+
+impl Iterator for Output {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let left = self.left.next()?;
+        Some(match self.channel {
+            Channel::Left => left,
+            Channel::Right => self.right.next().unwrap_or(left),
+        })
+    }
+}
+
+impl Source for Output {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1 // One side of a stereo pair
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+// One-pole high-pass at ~5Hz.
+#[derive(Debug, Clone, Default)]
+struct DcBlocker {
+    input: f32,
+    output: f32,
+}
+
+impl DcBlocker {
+    fn process(&mut self, x: f32, sample_rate: u32) -> f32 {
+        let r = 1.0 - 2.0 * PI * 5.0 / sample_rate as f32;
+        self.output = x - self.input + r * self.output;
+        self.input = x;
+        self.output
     }
 }
 
-fn triangle_wave(x: f32) -> f32 {
-    4.0 * (x + 0.25 - (x + 0.75).floor()).abs() - 1.0
+// Reads the block another step of a `Plan` wrote. The plan copies that
+// block into whatever input the bus is connected to before running the node
+// reading it, outside of a plan a bus is silent.
+#[derive(Debug, Clone)]
+pub struct Bus {
+    slot: usize,
+    sample_rate: u32,
 }
 
-impl Iterator for TriangleWave {
+impl Iterator for Bus {
     type Item = f32;
 
     fn next(&mut self) -> Option<f32> {
-        // Compute the next sample in the sine wave
-        let sample = triangle_wave((self.current_sample as f32 + self.phase_shift) * self.frequency / self.sample_rate as f32);
-        self.current_sample += 1;
-        Some(sample)
+        Some(0f32)
     }
 }
 
-impl Source for TriangleWave {
-    fn current_frame_len(&self) -> Option<usize> {
+impl Source for Bus {
+    fn current_span_len(&self) -> Option<usize> {
         None
     }
 
@@ -262,59 +3470,210 @@ impl Source for TriangleWave {
     }
 }
 
+// A graph flattened into steps run in order, a block at a time. Each step
+// writes into its own pre-allocated block, and reads the blocks of earlier
+// steps through buses, so a node feeding several others runs once. The
+// plan plays the sum of its outputs as stereo frames.
 #[derive(Debug, Clone)]
-pub struct SawtoothWave {
-    frequency: f32,
+pub struct Plan {
+    steps: Vec<Stream>,
+    // The first of each step's slots. A step has a slot, a block and its
+    // length, for each of its outputs.
+    firsts: Vec<usize>,
+    blocks: Vec<Vec<f32>>,
+    lens: Vec<usize>,
+    outputs: Vec<[usize; 2]>,
+    position: usize,
+    len: usize,
+    ended: bool,
     sample_rate: u32,
-    current_sample: u32,
-    phase_shift: f32,
 }
 
-impl SawtoothWave {
-    pub fn new() -> Self {
+impl Plan {
+    pub fn new(sample_rate: u32) -> Self {
         Self {
-            frequency: 0f32,
-            sample_rate: 44100,
-            current_sample: 0,
-            phase_shift: 0f32,
+            steps: Vec::new(),
+            firsts: Vec::new(),
+            blocks: Vec::new(),
+            lens: Vec::new(),
+            outputs: Vec::new(),
+            position: 0,
+            len: 0,
+            ended: false,
+            sample_rate,
         }
     }
-    
-    pub fn set_frequency(&mut self, freq: f32) {
-        self.frequency = freq;
+
+    // Adds a step running after all the steps added so far, and returns a
+    // bus reading what it writes. Buses may only be connected to later steps.
+    pub fn add_step(&mut self, node: Stream) -> Stream {
+        self.add_outputs_step(node, 1).remove(0)
     }
 
-    pub fn set_phase_shift(&mut self, shift: f32) {
-        self.phase_shift = shift;
+    // Adds a step writing `outputs` blocks at once, with a bus reading each.
+    pub fn add_outputs_step(&mut self, node: Stream, outputs: usize) -> Vec<Stream> {
+        let sample_rate = node.sample_rate();
+        let first = self.blocks.len();
+        self.steps.push(node);
+        self.firsts.push(first);
+        (first..first + outputs).map(|slot| {
+            self.blocks.push(vec![0f32; BLOCK_SIZE]);
+            self.lens.push(0);
+            Stream::Bus(Bus { slot, sample_rate })
+        }).collect()
+    }
+
+    // Adds a stereo pair to the mix the plan plays.
+    pub fn add_output(&mut self, left: Stream, right: Stream) {
+        let left = self.slot(left);
+        let right = self.slot(right);
+        self.outputs.push([left, right]);
+    }
+
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn steps(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn slot(&mut self, stream: Stream) -> usize {
+        match stream {
+            Stream::Bus(bus) => bus.slot,
+            stream => {
+                self.add_step(stream);
+                self.blocks.len() - 1
+            }
+        }
+    }
+
+    // Runs every step once. The plan ends with its shortest output.
+    fn run(&mut self) {
+        for (i, step) in self.steps.iter_mut().enumerate() {
+            let first = self.firsts[i];
+            let outputs = self.firsts.get(i + 1).unwrap_or(&self.blocks.len()) - first;
+            let (done, rest) = self.blocks.split_at_mut(first);
+            let (done_lens, rest_lens) = self.lens.split_at_mut(first);
+            step.for_each_input(&mut |input| input.feed(done, done_lens));
+            let len = step.process_outputs(&mut rest[..outputs]);
+            rest_lens[..outputs].iter_mut().for_each(|l| *l = len);
+        }
+        let lens = &self.lens;
+        self.len = self.outputs.iter().flatten().map(|&slot| lens[slot]).min().unwrap_or(0);
+        self.position = 0;
+        self.ended = self.len < BLOCK_SIZE;
+    }
+
+    // Runs the first block ahead of time, so the buffers nodes allocate on
+    // first use are there before the plan is handed to an audio thread.
+    pub fn prime(&mut self) {
+        if self.position == self.len && !self.ended {
+            self.run();
+        }
+    }
+
+    pub fn next_frame(&mut self) -> Option<[f32; 2]> {
+        if self.position == self.len {
+            if self.ended {
+                return None;
+            }
+            self.run();
+            if self.len == 0 {
+                return None;
+            }
+        }
+        let i = self.position;
+        self.position += 1;
+        let mut frame = [0f32; 2];
+        for output in &self.outputs {
+            frame[0] += self.blocks[output[0]][i];
+            frame[1] += self.blocks[output[1]][i];
+        }
+        Some(frame)
     }
 }
 
-fn sawtooth_wave(x: f32) -> f32 {
-    x % 1.0
+// The last stage before the sink or a file, whatever the graph outputs.
+// Non-finite samples are muted, DC is removed and a limiter linked across
+// both sides keeps the result under -1dBFS so a wrong wire can't blast
+// anyone's ears. Plays the mix of a plan's outputs as interleaved stereo.
+#[derive(Debug, Clone)]
+pub struct Master {
+    plan: Plan,
+    dc_blockers: [DcBlocker; 2],
+    lines: [DelayLine; 2],
+    limiter: Limiter,
+    pending: Option<f32>,
 }
 
-impl Iterator for SawtoothWave {
+impl Master {
+    pub fn new(plan: Plan) -> Self {
+        let mut limiter = Limiter::new();
+        limiter.set_threshold(-1.0);
+        limiter.set_lookahead(2.0);
+        // The lookahead and release are timed at the plan's rate
+        limiter.sample_rate = plan.sample_rate();
+        Self {
+            plan,
+            dc_blockers: Default::default(),
+            lines: Default::default(),
+            limiter,
+            pending: None,
+        }
+    }
+
+    // Allocates the master stage's buffers and runs the plan's first block,
+    // so neither allocates once playing starts.
+    pub fn prime(&mut self) {
+        self.plan.prime();
+        let len = self.limiter.window_len();
+        self.limiter.prepare(len);
+        self.lines.iter_mut().for_each(|line| line.reserve(len + 4));
+    }
+}
+
+impl Iterator for Master {
     type Item = f32;
 
     fn next(&mut self) -> Option<f32> {
-        // Compute the next sample in the sine wave
-        let sample = sawtooth_wave((self.current_sample as f32 + self.phase_shift) * self.frequency / self.sample_rate as f32);
-        self.current_sample += 1;
-        Some(sample)
+        if let Some(right) = self.pending.take() {
+            return Some(right);
+        }
+
+        let mut frame = self.plan.next_frame()?;
+        let sample_rate = self.plan.sample_rate();
+        for (x, dc_blocker) in frame.iter_mut().zip(self.dc_blockers.iter_mut()) {
+            // Clamped well above full scale, only to keep the filter state finite.
+            let guarded = if x.is_finite() { x.clamp(-1e4, 1e4) } else { 0.0 };
+            *x = dc_blocker.process(guarded, sample_rate);
+        }
+
+        let gain = self.limiter.next_gain(frame[0].abs().max(frame[1].abs()));
+        let latency = self.limiter.latency();
+        for (x, line) in frame.iter_mut().zip(self.lines.iter_mut()) {
+            line.reserve(latency + 4);
+            line.push(*x);
+            *x = (line.at(latency + 1) * gain).clamp(-1.0, 1.0);
+        }
+
+        self.pending = Some(frame[1]);
+        Some(frame[0])
     }
 }
 
-impl Source for SawtoothWave {
-    fn current_frame_len(&self) -> Option<usize> {
+impl Source for Master {
+    fn current_span_len(&self) -> Option<usize> {
         None
     }
 
     fn channels(&self) -> u16 {
-        1 // Mono sound
+        2 // Interleaved stereo
     }
 
     fn sample_rate(&self) -> u32 {
-        self.sample_rate
+        self.plan.sample_rate()
     }
 
     fn total_duration(&self) -> Option<std::time::Duration> {
@@ -322,13 +3681,12 @@ impl Source for SawtoothWave {
     }
 }
 
-
 // A struct that generates a sine wave at a given frequency and sample rate modulated by.
 #[derive(Debug, Clone)]
 pub struct ModulatedSineWave {
     frequency: f32,
     sample_rate: u32,
-    modulator: Box<Stream>,
+    modulator: Input,
     current_sample: f32,
 }
 
@@ -337,13 +3695,13 @@ impl ModulatedSineWave {
         Self {
             frequency: 0f32,
             sample_rate: 44100,
-            modulator: Box::new(Stream::Empty(Empty::new())),
+            modulator: Input::default(),
             current_sample: 0f32,
         }
     }
 
     pub fn set_modulator(&mut self, modulator: Stream) {
-        self.modulator = Box::new(modulator);
+        self.modulator.set(modulator);
     }
 
     pub fn set_frequency(&mut self, freq: f32) {
@@ -357,7 +3715,7 @@ impl Iterator for ModulatedSineWave {
     fn next(&mut self) -> Option<f32> {
         // Compute the next sample in the sine wave
         self.modulator.next().map(|a| { 
-            let sample = 1f32 * (self.current_sample as f32 * 2.0 * PI * self.frequency / self.sample_rate as f32).sin();
+            let sample = 1f32 * (self.current_sample * 2.0 * PI * self.frequency / self.sample_rate as f32).sin();
             self.current_sample = (self.current_sample + 1.0 + a) % self.sample_rate as f32;
             sample
         })
@@ -365,7 +3723,7 @@ impl Iterator for ModulatedSineWave {
 }
 
 impl Source for ModulatedSineWave {
-    fn current_frame_len(&self) -> Option<usize> {
+    fn current_span_len(&self) -> Option<usize> {
         None
     }
 
@@ -386,8 +3744,8 @@ impl Source for ModulatedSineWave {
 #[derive(Debug, Clone)]
 pub struct Mix {
     sample_rate: u32,
-    stream_a: Box<Stream>,
-    stream_b: Box<Stream>,
+    stream_a: Input,
+    stream_b: Input,
     p: f32,
 }
 
@@ -407,18 +3765,18 @@ impl Mix {
     pub fn new() -> Self {
         Self {
             sample_rate: 44100,
-            stream_a: Box::new(Stream::Empty(Empty::new())),
-            stream_b: Box::new(Stream::Empty(Empty::new())),
+            stream_a: Input::default(),
+            stream_b: Input::default(),
             p: 0.5
         }
     }
 
     pub fn set_stream_a(&mut self, modulator: Stream) {
-        self.stream_a = Box::new(modulator);
+        self.stream_a.set(modulator);
     }
 
     pub fn set_stream_b(&mut self, modulator: Stream) {
-        self.stream_b = Box::new(modulator);
+        self.stream_b.set(modulator);
     }
 
     pub fn set_p(&mut self, p: f32) {
@@ -427,7 +3785,7 @@ impl Mix {
 }
 
 impl Source for Mix {
-    fn current_frame_len(&self) -> Option<usize> {
+    fn current_span_len(&self) -> Option<usize> {
         None
     }
 
@@ -458,6 +3816,10 @@ impl Const {
         }
     }
 
+    pub fn val(&self) -> f32 {
+        self.val
+    }
+
     pub fn set_val(&mut self, val: f32) {
         self.val = val;
     }
@@ -472,7 +3834,7 @@ impl Iterator for Const {
 }
 
 impl Source for Const {
-    fn current_frame_len(&self) -> Option<usize> {
+    fn current_span_len(&self) -> Option<usize> {
         None
     }
 
@@ -507,7 +3869,7 @@ impl Iterator for Empty {
 }
 
 impl Source for Empty {
-    fn current_frame_len(&self) -> Option<usize> {
+    fn current_span_len(&self) -> Option<usize> {
         None
     }
 
@@ -533,7 +3895,7 @@ pub struct Envelope {
     s:  f32,
     sd: f32,
     rd: f32,
-    stream: Box<Stream>,
+    stream: Input,
     sample_rate: u32,
     current_sample: u32,
 }
@@ -549,17 +3911,17 @@ impl Envelope {
             s: 0.6,
             sd: 2.0,
             rd: 1.0,
-            stream: Box::new(Stream::default()),
+            stream: Input::default(),
         }
     }
     
-    pub fn set_stream(&mut self, stream: Stream) { self.stream = Box::new(stream); }
-    pub fn set_a(&mut self, v: f32) -> () { self.a = v; } 
-    pub fn set_ad(&mut self, v: f32) -> () { self.ad = v; } 
-    pub fn set_dd(&mut self, v: f32) -> () { self.dd = v; } 
-    pub fn set_s(&mut self, v: f32) -> () { self.s = v; } 
-    pub fn set_sd(&mut self, v: f32) -> () { self.sd = v; } 
-    pub fn set_rd(&mut self, v: f32) -> () { self.rd = v; } 
+    pub fn set_stream(&mut self, stream: Stream) { self.stream.set(stream); }
+    pub fn set_a(&mut self, v: f32) { self.a = v; } 
+    pub fn set_ad(&mut self, v: f32) { self.ad = v; } 
+    pub fn set_dd(&mut self, v: f32) { self.dd = v; } 
+    pub fn set_s(&mut self, v: f32) { self.s = v; } 
+    pub fn set_sd(&mut self, v: f32) { self.sd = v; } 
+    pub fn set_rd(&mut self, v: f32) { self.rd = v; } 
 }
 
 fn lerp(a: f32, b: f32, f: f32) -> f32 { a * (1.0-f) + b * f }
@@ -573,16 +3935,16 @@ impl Iterator for Envelope {
         self.stream.next().map(|sample: f32| {
             sample * {
             if t < self.ad { lerp(0.0, self.a, t/self.ad) }
-            else if (t < self.ad + self.dd) { lerp(self.a,self.s, (t-self.ad)/self.dd) }
-            else if (t < self.ad + self.dd + self.sd) { self.s }
-            else if (t < self.ad + self.dd + self.sd + self.rd) { lerp(self.s,0.0, (t-self.ad-self.dd-self.sd)/self.rd) }
+            else if t < self.ad + self.dd { lerp(self.a,self.s, (t-self.ad)/self.dd) }
+            else if t < self.ad + self.dd + self.sd { self.s }
+            else if t < self.ad + self.dd + self.sd + self.rd { lerp(self.s,0.0, (t-self.ad-self.dd-self.sd)/self.rd) }
             else { 0.0 }}
         }) 
     }
 }
 
 impl Source for Envelope {
-    fn current_frame_len(&self) -> Option<usize> {
+    fn current_span_len(&self) -> Option<usize> {
         None
     }
 
@@ -602,7 +3964,7 @@ impl Source for Envelope {
 #[derive(Debug, Clone)]
 pub struct Perlin {
     scale: f32,
-    perl: noise::Perlin,
+    perl: Box<noise::Perlin>,
     sample_rate: u32,
     current_sample: u32,
 }
@@ -612,7 +3974,7 @@ impl Perlin {
         Self {
             sample_rate: 44100,
             current_sample: 0,
-            perl: noise::Perlin::new(69),
+            perl: Box::new(noise::Perlin::new(69)),
             scale: 1.0
         }
     }
@@ -632,7 +3994,7 @@ impl Iterator for Perlin {
 }
 
 impl Source for Perlin {
-    fn current_frame_len(&self) -> Option<usize> {
+    fn current_span_len(&self) -> Option<usize> {
         None
     }
 
@@ -650,12 +4012,13 @@ impl Source for Perlin {
 }
 
 #[derive(Debug, Clone)]
-pub struct WhiteNoise { sample_rate: u32 }
+pub struct WhiteNoise { sample_rate: u32, rng: Rng }
 
 impl WhiteNoise {
     pub fn new() -> Self {
         Self {
             sample_rate: 44100,
+            rng: Rng::new(),
         }
     }
 }
@@ -664,12 +4027,12 @@ impl Iterator for WhiteNoise {
     type Item = f32;
 
     fn next(&mut self) -> Option<f32> { 
-        Some(rand::random::<f32>() * 2.0 - 1.0)
+        Some(self.rng.next_f32() * 2.0 - 1.0)
     }
 }
 
 impl Source for WhiteNoise {
-    fn current_frame_len(&self) -> Option<usize> {
+    fn current_span_len(&self) -> Option<usize> {
         None
     }
 
@@ -686,45 +4049,115 @@ impl Source for WhiteNoise {
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct Add {
-    sample_rate: u32,
-    stream_a: Box<Stream>,
-    stream_b: Box<Stream>,
+/// Values a host changes while a patch plays, from any thread. Parameter
+/// nodes read a value by name and Note nodes the last note played, both
+/// through atomics, so setting them never waits on the audio thread and
+/// the audio thread never waits on them.
+#[derive(Debug, Clone, Default)]
+pub struct Controls {
+    // The index of each parameter's value, by name.
+    params: HashMap<String, usize>,
+    values: Vec<Arc<AtomicU32>>,
+    note: Arc<NoteState>,
 }
 
-impl Iterator for Add {
-    type Item = f32;
+#[derive(Debug, Default)]
+struct NoteState {
+    pitch: AtomicU32,
+    velocity: AtomicU32,
+    gate: AtomicBool,
+    // Counts notes played, so a note played while the last one is still
+    // held can retrigger the gate.
+    count: AtomicU32,
+}
 
-    fn next(&mut self) -> Option<f32> {
-        // Compute the next sample in the sine wave
-        self.stream_a.next().and_then(|a| { 
-        self.stream_b.next().map(|b| {
-            a + b
-        }) })
+impl Controls {
+    /// Sets a parameter, returns false when no Parameter node reads `name`.
+    pub fn set(&self, name: &str, value: f32) -> bool {
+        self.params.get(name).map_or(false, |&index| self.set_index(index, value))
+    }
+
+    pub fn get(&self, name: &str) -> Option<f32> {
+        let value = self.values.get(*self.params.get(name)?)?;
+        Some(f32::from_bits(value.load(Ordering::Relaxed)))
+    }
+
+    // Parameters by index, so a parameter can be sent to the audio thread
+    // without its name.
+    pub(crate) fn index(&self, name: &str) -> Option<usize> {
+        self.params.get(name).copied()
+    }
+
+    pub(crate) fn set_index(&self, index: usize, value: f32) -> bool {
+        match self.values.get(index) {
+            Some(param) => {
+                param.store(value.to_bits(), Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The names of the parameters the patch reads.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.params.keys().map(|name| name.as_str())
+    }
+
+    /// Plays a MIDI note, `velocity` goes from 0 to 1.
+    pub fn note_on(&self, note: u8, velocity: f32) {
+        let pitch = 440.0 * 2f32.powf((note as f32 - 69.0) / 12.0);
+        self.note.pitch.store(pitch.to_bits(), Ordering::Relaxed);
+        self.note.velocity.store(velocity.clamp(0.0, 1.0).to_bits(), Ordering::Relaxed);
+        self.note.count.fetch_add(1, Ordering::Release);
+        self.note.gate.store(true, Ordering::Release);
+    }
+
+    pub fn note_off(&self) {
+        self.note.gate.store(false, Ordering::Release);
+    }
+
+    // The value Parameter nodes named `name` read, nodes sharing a name
+    // share the value and it starts at the first node's default.
+    pub(crate) fn param(&mut self, name: &str, default: f32) -> Arc<AtomicU32> {
+        let values = &mut self.values;
+        let index = *self.params.entry(name.to_string()).or_insert_with(|| {
+            values.push(Arc::new(AtomicU32::new(default.to_bits())));
+            values.len() - 1
+        });
+        self.values[index].clone()
     }
 }
 
-impl Add {
+// A value set through `Controls`, glided over a few milliseconds so changes
+// don't click.
+#[derive(Debug, Clone)]
+pub struct Parameter {
+    value: Arc<AtomicU32>,
+    smoother: Smoother,
+    sample_rate: u32,
+}
+
+impl Parameter {
     pub fn new() -> Self {
-        Self {
-            sample_rate: 44100,
-            stream_a: Box::new(Stream::Empty(Empty::new())),
-            stream_b: Box::new(Stream::Empty(Empty::new())),
-        }
+        Self { value: Arc::default(), smoother: Smoother::default(), sample_rate: 44100 }
     }
 
-    pub fn set_stream_a(&mut self, modulator: Stream) {
-        self.stream_a = Box::new(modulator);
+    pub fn set_controls(&mut self, controls: &mut Controls, name: &str, default: f32) {
+        self.value = controls.param(name, default);
     }
+}
 
-    pub fn set_stream_b(&mut self, modulator: Stream) {
-        self.stream_b = Box::new(modulator);
+impl Iterator for Parameter {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let target = f32::from_bits(self.value.load(Ordering::Relaxed));
+        Some(self.smoother.next(target, self.sample_rate))
     }
 }
 
-impl Source for Add {
-    fn current_frame_len(&self) -> Option<usize> {
+impl Source for Parameter {
+    fn current_span_len(&self) -> Option<usize> {
         None
     }
 
@@ -741,45 +4174,178 @@ impl Source for Add {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoteOutput {
+    Gate,
+    Pitch,
+    Velocity,
+}
+
+// The last note played through `Controls`. The gate is 1 while the note is
+// held and drops for one sample when another note is played over it, the
+// pitch is in Hz.
 #[derive(Debug, Clone)]
-pub struct Multiply {
+pub struct Note {
+    note: Arc<NoteState>,
+    output: NoteOutput,
+    count: u32,
     sample_rate: u32,
-    stream_a: Box<Stream>,
-    stream_b: Box<Stream>,
 }
 
-impl Iterator for Multiply {
+impl Note {
+    pub fn new() -> Self {
+        Self { note: Arc::default(), output: NoteOutput::Gate, count: 0, sample_rate: 44100 }
+    }
+
+    pub fn set_controls(&mut self, controls: &Controls) {
+        self.note = controls.note.clone();
+        self.count = self.note.count.load(Ordering::Acquire);
+    }
+
+    pub fn set_output(&mut self, output: NoteOutput) { self.output = output; }
+}
+
+impl Iterator for Note {
     type Item = f32;
 
     fn next(&mut self) -> Option<f32> {
-        // Compute the next sample in the sine wave
-        self.stream_a.next().and_then(|a| { 
-        self.stream_b.next().map(|b| {
-            a * b
-        }) })
+        Some(match self.output {
+            NoteOutput::Gate => {
+                let gate = self.note.gate.load(Ordering::Acquire);
+                let count = self.note.count.load(Ordering::Acquire);
+                let retrigger = count != self.count;
+                self.count = count;
+                if gate && !retrigger { 1.0 } else { 0.0 }
+            }
+            NoteOutput::Pitch => f32::from_bits(self.note.pitch.load(Ordering::Relaxed)),
+            NoteOutput::Velocity => f32::from_bits(self.note.velocity.load(Ordering::Relaxed)),
+        })
     }
 }
 
-impl Multiply {
-    pub fn new() -> Self {
+impl Source for Note {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1 // Mono sound
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+/// The most outputs a [`SynthNode`] may have.
+pub const MAX_OUTPUTS: usize = 16;
+
+// A node written against the `SynthNode` trait. Its stream inputs are read a
+// block at a time and handed to the node together. In a plan the node is a
+// single step writing a block for each output. Played on its own, it plays
+// its first output.
+#[derive(Debug, Clone)]
+pub struct Custom {
+    node: Box<dyn SynthNode>,
+    inputs: Vec<Input>,
+    params: Vec<f32>,
+    blocks: Vec<Vec<f32>>,
+    outputs: Vec<Vec<f32>>,
+    position: usize,
+    len: usize,
+    ended: bool,
+    sample_rate: u32,
+}
+
+impl Custom {
+    pub fn new(node: Box<dyn SynthNode>) -> Self {
+        let ports = node.ports();
+        let streams = ports.iter().filter(|port| matches!(port, Port::Stream(_))).count();
+        let params = ports.iter().filter_map(|port| match port {
+            Port::Param { default, .. } => Some(*default),
+            Port::Stream(_) => None,
+        }).collect();
+        let block = || Vec::with_capacity(BLOCK_SIZE);
         Self {
+            inputs: (0..streams).map(|_| Input::default()).collect(),
+            params,
+            blocks: (0..streams).map(|_| block()).collect(),
+            outputs: node.outputs().iter().map(|_| block()).collect(),
+            position: 0,
+            len: 0,
+            ended: false,
             sample_rate: 44100,
-            stream_a: Box::new(Stream::Empty(Empty::new())),
-            stream_b: Box::new(Stream::Empty(Empty::new())),
+            node,
         }
     }
 
-    pub fn set_stream_a(&mut self, modulator: Stream) {
-        self.stream_a = Box::new(modulator);
+    pub fn node(&self) -> &dyn SynthNode { self.node.as_ref() }
+
+    // Inputs and params are numbered separately, in the order of the ports.
+    pub fn set_input(&mut self, index: usize, stream: Stream) { self.inputs[index].set(stream); }
+    pub fn set_param(&mut self, index: usize, value: f32) { self.params[index] = value; }
+    // Runs the node on the next block of its inputs, as long as the
+    // shortest of them, and returns its length. The node writes the start
+    // of each block in `outputs`, one for each of its outputs.
+    fn run(&mut self, outputs: &mut [Vec<f32>]) -> usize {
+        if self.ended {
+            return 0;
+        }
+        let mut len = BLOCK_SIZE;
+        for (input, block) in self.inputs.iter_mut().zip(self.blocks.iter_mut()) {
+            block.clear();
+            while block.len() < len {
+                match input.next() {
+                    Some(x) => block.push(x),
+                    None => break,
+                }
+            }
+            len = block.len();
+        }
+        for block in self.blocks.iter_mut() {
+            block.truncate(len);
+        }
+
+        // Slices rather than the blocks themselves, so the node can't resize
+        // them, gathered without allocating.
+        let count = outputs.len().min(MAX_OUTPUTS);
+        let mut blocks = outputs.iter_mut();
+        let mut slices: [&mut [f32]; MAX_OUTPUTS] = [(); MAX_OUTPUTS].map(|_| match blocks.next() {
+            Some(block) => &mut block[..len],
+            None => &mut [],
+        });
+        slices.iter_mut().for_each(|slice| slice.fill(0.0));
+        self.node.process(&self.blocks, &self.params, &mut slices[..count], self.sample_rate);
+        self.ended = len < BLOCK_SIZE;
+        len
     }
+}
 
-    pub fn set_stream_b(&mut self, modulator: Stream) {
-        self.stream_b = Box::new(modulator);
+impl Iterator for Custom {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.position == self.len {
+            let mut outputs = std::mem::take(&mut self.outputs);
+            outputs.iter_mut().for_each(|output| output.resize(BLOCK_SIZE, 0.0));
+            self.len = self.run(&mut outputs);
+            self.outputs = outputs;
+            self.position = 0;
+            if self.len == 0 {
+                return None;
+            }
+        }
+        self.position += 1;
+        Some(self.outputs[0][self.position - 1])
     }
 }
 
-impl Source for Multiply {
-    fn current_frame_len(&self) -> Option<usize> {
+impl Source for Custom {
+    fn current_span_len(&self) -> Option<usize> {
         None
     }
 
@@ -794,4 +4360,454 @@ impl Source for Multiply {
     fn total_duration(&self) -> Option<std::time::Duration> {
         None
     }
-}
\ No newline at end of file
+}
+
+// Sums two streams.
+#[derive(Debug, Clone, Default)]
+pub struct Add;
+
+impl SynthNode for Add {
+    fn name(&self) -> &'static str { "Add" }
+    fn category(&self) -> &'static str { "Math" }
+    fn ports(&self) -> Vec<Port> { vec![Port::stream("A"), Port::stream("B")] }
+
+    fn process(&mut self, inputs: &[Vec<f32>], _params: &[f32], outputs: &mut [&mut [f32]], _sample_rate: u32) {
+        for ((out, a), b) in outputs[0].iter_mut().zip(&inputs[0]).zip(&inputs[1]) {
+            *out = a + b;
+        }
+    }
+}
+
+// Multiplies two streams, for ring modulation or a VCA.
+#[derive(Debug, Clone, Default)]
+pub struct Multiply;
+
+impl SynthNode for Multiply {
+    fn name(&self) -> &'static str { "Multiply" }
+    fn category(&self) -> &'static str { "Math" }
+    fn ports(&self) -> Vec<Port> { vec![Port::stream("A"), Port::stream("B")] }
+
+    fn process(&mut self, inputs: &[Vec<f32>], _params: &[f32], outputs: &mut [&mut [f32]], _sample_rate: u32) {
+        for ((out, a), b) in outputs[0].iter_mut().zip(&inputs[0]).zip(&inputs[1]) {
+            *out = a * b;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn constant(val: f32) -> Stream {
+        let mut constant = Const::new();
+        constant.set_val(val);
+        Stream::Const(constant)
+    }
+
+    fn sine(frequency: f32) -> Stream {
+        let mut sine = SineWave::new();
+        sine.set_frequency(frequency);
+        Stream::SineWave(sine)
+    }
+
+    // Plays `samples` once, then silence.
+    fn clip(samples: Vec<f32>) -> Stream {
+        let mut sampler = Sampler::new();
+        sampler.set_sample(Arc::new(SampleBuffer { samples, sample_rate: 44100 }));
+        sampler.set_rate(constant(1.0));
+        Stream::Sampler(sampler)
+    }
+
+    fn render(stream: impl Iterator<Item = f32>, len: usize) -> Vec<f32> {
+        stream.take(len).collect()
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|x| x * x).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples.iter().fold(0.0, |peak, x| peak.max(x.abs()))
+    }
+
+    #[test]
+    fn wavetable_morphs_from_sine_to_square() {
+        let play = |position| {
+            let mut wave = Wavetable::new();
+            wave.set_bank(Arc::new(WavetableBank::shapes()));
+            wave.set_frequency(100.0);
+            wave.set_position(constant(position));
+            rms(&render(wave, 44100))
+        };
+        assert!((play(0.0) - 0.5f32.sqrt()).abs() < 0.01);
+        assert!(play(1.0) > 0.95);
+    }
+
+    #[test]
+    fn sampler_waits_for_a_connected_gate() {
+        let samples = vec![0.5; 100];
+        let played = render(clip(samples.clone()), 200);
+        assert_eq!(played[..100], samples[..]);
+        assert!(played[100..].iter().all(|&x| x == 0.0));
+
+        let mut sampler = Sampler::new();
+        sampler.set_sample(Arc::new(SampleBuffer { samples, sample_rate: 44100 }));
+        sampler.set_rate(constant(1.0));
+        sampler.set_gate(constant(0.0));
+        assert!(render(sampler, 200).iter().all(|&x| x == 0.0));
+    }
+
+    #[test]
+    fn pluck_decays_and_stays_stable_up_high() {
+        for frequency in [220.0, 40000.0] {
+            let mut pluck = Pluck::new();
+            pluck.set_frequency(frequency);
+            pluck.set_damping(0.5);
+            pluck.set_brightness(0.7);
+            let out = render(pluck, 44100);
+            assert!(out.iter().all(|x| x.is_finite()) && peak(&out) <= 1.0);
+            assert!(rms(&out[40000..]) < rms(&out[..4410]) / 10.0);
+        }
+    }
+
+    #[test]
+    fn additive_presets_stop_below_nyquist() {
+        let partials = AdditivePreset::Saw.partials(1000, 22050.0 / 1000.0);
+        assert_eq!(partials.len(), 22);
+        assert_eq!(AdditivePreset::Square.partials(usize::MAX, f32::INFINITY).len(), AdditivePreset::MAX_PARTIALS);
+
+        let mut wave = Additive::new();
+        wave.set_frequency(440.0);
+        wave.set_partials(vec![Partial::new(1.0, 1.0)]);
+        assert!((rms(&render(wave, 44100)) - 0.5f32.sqrt()).abs() < 0.01);
+    }
+
+    #[test]
+    fn supersaw_copies_play_the_same() {
+        let mut wave = Supersaw::new();
+        wave.set_frequency(110.0);
+        wave.set_voices(7);
+        wave.set_detune(50.0);
+        let out = render(wave.clone(), 4410);
+        assert_eq!(out, render(wave, 4410));
+        assert!(rms(&out) > 0.1 && peak(&out) < 2.0);
+    }
+
+    #[test]
+    fn supersaw_spreads_its_voices() {
+        let side = |channel| {
+            let mut wave = Supersaw::new();
+            wave.set_frequency(110.0);
+            wave.set_voices(7);
+            wave.set_detune(0.5);
+            wave.set_spread(1.0);
+            wave.set_channel(channel);
+            render(wave, 4410)
+        };
+        let (left, right) = (side(Channel::Left), side(Channel::Right));
+        assert_ne!(left, right);
+        assert!(peak(&left) < 2.0 && peak(&right) < 2.0);
+    }
+
+    #[test]
+    fn hard_sync_restarts_the_cycle() {
+        let play = |synced| {
+            let mut wave = SawtoothWave::new();
+            wave.set_frequency(330.0);
+            if synced {
+                let mut sync = SquareWave::new();
+                sync.set_frequency(100.0);
+                wave.set_sync(Stream::SquareWave(sync));
+            }
+            render(wave, 2000)
+        };
+        // The sync oscillator repeats every 441 samples, so does the synced one
+        let periodic = |out: &[f32]| (1000..1441).all(|i| (out[i] - out[i + 441]).abs() < 1e-4);
+        assert!(periodic(&play(true)));
+        assert!(!periodic(&play(false)));
+    }
+
+    #[test]
+    fn granular_grains_read_the_sample() {
+        let mut wave = Granular::new();
+        wave.set_source(GrainSource::Sample);
+        wave.set_sample(Arc::new(SampleBuffer { samples: vec![1.0; 44100], sample_rate: 44100 }));
+        wave.set_grain_size(100.0);
+        wave.set_density(20.0);
+        wave.set_position(constant(0.5));
+        let out = render(wave, 44100);
+        assert!(out.iter().all(|&x| (0.0..=1.0).contains(&x)));
+        assert!(rms(&out[4410..]) > 0.5);
+    }
+
+    #[test]
+    fn biquad_low_pass_attenuates_above_cutoff() {
+        let play = |frequency| {
+            let mut filter = Biquad::new();
+            filter.set_stream(sine(frequency));
+            filter.set_cutoff(constant(500.0));
+            filter.set_q(constant(0.707));
+            rms(&render(filter, 44100)[4410..])
+        };
+        assert!((play(100.0) - 0.5f32.sqrt()).abs() < 0.05);
+        assert!(play(5000.0) < 0.01);
+    }
+
+    #[test]
+    fn state_variable_and_ladder_filters_split_the_spectrum() {
+        let svf = |output, frequency| {
+            let mut filter = StateVariableFilter::new();
+            filter.set_stream(sine(frequency));
+            filter.set_cutoff(constant(1000.0));
+            filter.set_q(constant(0.707));
+            filter.set_output(output);
+            rms(&render(filter, 44100)[4410..])
+        };
+        assert!(svf(SvfOutput::LowPass, 100.0) > 0.6 && svf(SvfOutput::HighPass, 100.0) < 0.05);
+        assert!(svf(SvfOutput::HighPass, 10000.0) > 0.6 && svf(SvfOutput::LowPass, 10000.0) < 0.05);
+
+        let mut ladder = LadderFilter::new();
+        ladder.set_stream(sine(8000.0));
+        ladder.set_cutoff(constant(500.0));
+        ladder.set_resonance(constant(0.0));
+        assert!(rms(&render(ladder, 44100)[4410..]) < 0.01);
+    }
+
+    #[test]
+    fn delay_echo_lands_at_the_set_time() {
+        let mut delay = Delay::new();
+        delay.set_stream(clip(vec![1.0]));
+        delay.set_time(constant(10.0));
+        delay.set_mix(1.0);
+        let out = render(delay, 2000);
+        let loudest = (0..out.len()).max_by(|&a, &b| out[a].abs().total_cmp(&out[b].abs())).unwrap();
+        assert_eq!(loudest, 441);
+
+        // Delays under two samples and NaN stay in range
+        for time in [0.03, f32::NAN] {
+            let mut delay = Delay::new();
+            delay.set_stream(sine(440.0));
+            delay.set_time(constant(time));
+            assert!(render(delay, 100).iter().all(|x| x.is_finite()));
+        }
+    }
+
+    #[test]
+    fn reverb_tail_rings_and_decays() {
+        let mut reverb = Reverb::new();
+        reverb.set_stream(clip(vec![1.0; 441]));
+        reverb.set_room_size(0.5);
+        reverb.set_mix(1.0);
+        let out = render(reverb, 3 * 44100);
+        assert!(out.iter().all(|x| x.is_finite()));
+        assert!(rms(&out[4410..8820]) > 1e-3);
+        assert!(rms(&out[2 * 44100..]) < rms(&out[4410..8820]) / 10.0);
+    }
+
+    #[test]
+    fn modulation_effects_are_dry_at_zero_mix() {
+        let dry = render(sine(440.0), 4410);
+        let effects: [fn(f32) -> Stream; 3] = [
+            |mix| {
+                let mut fx = Chorus::new();
+                fx.set_stream(sine(440.0));
+                fx.set_rate(1.0);
+                fx.set_depth(1.0);
+                fx.set_mix(mix);
+                Stream::Chorus(fx)
+            },
+            |mix| {
+                let mut fx = Flanger::new();
+                fx.set_stream(sine(440.0));
+                fx.set_rate(1.0);
+                fx.set_depth(1.0);
+                fx.set_mix(mix);
+                Stream::Flanger(fx)
+            },
+            |mix| {
+                let mut fx = Phaser::new();
+                fx.set_stream(sine(440.0));
+                fx.set_rate(1.0);
+                fx.set_depth(1.0);
+                fx.set_mix(mix);
+                Stream::Phaser(fx)
+            },
+        ];
+        for effect in effects {
+            assert_eq!(render(effect(0.0), 4410), dry);
+            let wet = render(effect(1.0), 4410);
+            assert_ne!(wet, dry);
+            assert!(peak(&wet) < 2.0);
+        }
+    }
+
+    #[test]
+    fn hard_clip_distortion_stays_under_full_scale() {
+        let mut distortion = Distortion::new();
+        distortion.set_stream(sine(100.0));
+        distortion.set_drive(constant(10.0));
+        distortion.set_curve(ShaperCurve::HardClip);
+        let out = render(distortion, 4410);
+        assert!(peak(&out) <= 1.0);
+        assert!(out.iter().filter(|x| x.abs() > 0.99).count() > out.len() * 3 / 4);
+    }
+
+    #[test]
+    fn bitcrusher_quantises_to_two_to_the_bits_levels() {
+        let mut crusher = Bitcrusher::new();
+        crusher.set_stream(sine(100.0));
+        crusher.set_bits(3.0);
+        crusher.set_rate(constant(44100.0));
+        let mut levels: Vec<f32> = render(crusher, 4410);
+        assert!(levels.iter().all(|x| (x * 4.0).fract() == 0.0));
+        levels.sort_by(f32::total_cmp);
+        levels.dedup();
+        assert_eq!(levels.len(), 8);
+    }
+
+    #[test]
+    fn compressor_follows_its_ratio() {
+        // Output level in dB once settled, for a steady input level
+        let compress = |level: f32, knee| {
+            let mut compressor = Compressor::new();
+            compressor.set_stream(constant(db_to_gain(level)));
+            compressor.set_threshold(-20.0);
+            compressor.set_ratio(4.0);
+            compressor.set_knee(knee);
+            gain_to_db(*render(compressor, 44100).last().unwrap())
+        };
+        for knee in [0.0, 6.0] {
+            assert!((compress(0.0, knee) + 15.0).abs() < 0.01);
+            assert!((compress(-40.0, knee) + 40.0).abs() < 0.01);
+        }
+        assert!((compress(-20.0, 0.0) + 20.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn limiter_never_exceeds_its_ceiling() {
+        let mut loud = Mix::new();
+        loud.set_stream_a(sine(440.0));
+        loud.set_stream_b(sine(1000.0));
+        let mut limiter = Limiter::new();
+        limiter.set_stream(Stream::Mix(loud));
+        limiter.set_threshold(-6.0);
+        limiter.set_lookahead(5.0);
+        limiter.set_makeup(12.0);
+        let out = render(limiter, 44100);
+        assert!(peak(&out) <= db_to_gain(-6.0));
+        assert!(rms(&out) > 0.1);
+    }
+
+    fn master(stream: Stream) -> Master {
+        let mut plan = Plan::new(44100);
+        plan.add_output(stream.clone(), stream);
+        Master::new(plan)
+    }
+
+    #[test]
+    fn master_silences_nan_and_removes_dc() {
+        assert!(render(master(constant(f32::NAN)), 4410).iter().all(|&x| x == 0.0));
+
+        let out = render(master(constant(0.5)), 2 * 44100);
+        assert!(peak(&out[44100..]) < 1e-3);
+
+        let mut saw = SawtoothWave::new();
+        saw.set_frequency(100.0);
+        let out = render(master(Stream::SawtoothWave(saw)), 2 * 44100);
+        assert!((out[44100..].iter().sum::<f32>() / 44100.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn pan_is_constant_power_and_width_zero_is_mono() {
+        for position in [-1.0, -0.5, 0.0, 0.3, 1.0] {
+            let side = |channel| {
+                let mut pan = Pan::new();
+                pan.set_stream(constant(1.0));
+                pan.set_pan(constant(position));
+                pan.set_channel(channel);
+                render(pan, 1)[0]
+            };
+            let (left, right) = (side(Channel::Left), side(Channel::Right));
+            assert!((left * left + right * right - 1.0).abs() < 1e-5);
+        }
+
+        let side = |channel| {
+            let mut width = Width::new();
+            width.set_left(sine(440.0));
+            width.set_right(sine(660.0));
+            width.set_width(0.0);
+            width.set_channel(channel);
+            render(width, 1000)
+        };
+        assert_eq!(side(Channel::Left), side(Channel::Right));
+    }
+
+    #[test]
+    fn every_output_gets_its_own_file() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("synthi-outputs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let paths: Vec<String> = ["kick", "lead"].iter().map(|name| dir.join(format!("{}.wav", name)).to_string_lossy().into_owned()).collect();
+        let files = paths.iter().zip([110.0, 440.0]).map(|(path, frequency)| (path.clone(), master(sine(frequency)))).collect();
+        write_wavs(files, 0.5)?;
+        for path in &paths {
+            let reader = hound::WavReader::open(path)?;
+            assert_eq!((reader.spec().channels, reader.duration()), (2, 22050));
+        }
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn nodes_run_at_the_rate_they_are_given() {
+        let mut wave = sine(1000.0);
+        wave.set_sample_rate(48000);
+        let out = render(wave, 100);
+        assert!((out[12] - 1.0).abs() < 1e-4);
+        assert!((out[0] - out[48]).abs() < 1e-4);
+    }
+
+    // A sine wave through 39 low-pass filters, 40 nodes in all. With a plan
+    // each node is a step reading the one before through a bus, without
+    // one they are nested in each other.
+    fn chain(mut plan: Option<&mut Plan>) -> Stream {
+        let mut sine = SineWave::new();
+        sine.set_frequency(220.0);
+        let mut stream = Stream::SineWave(sine);
+        for i in 0..39 {
+            if let Some(plan) = plan.as_deref_mut() {
+                stream = plan.add_step(stream);
+            }
+            let mut filter = Biquad::new();
+            filter.set_stream(stream);
+            filter.set_cutoff(constant(2000.0 + 100.0 * i as f32));
+            filter.set_q(constant(0.7));
+            stream = Stream::Biquad(filter);
+        }
+        stream
+    }
+
+    fn planned_chain() -> Plan {
+        let mut plan = Plan::new(44100);
+        let stream = chain(Some(&mut plan));
+        let bus = plan.add_step(stream);
+        plan.add_output(bus.clone(), bus);
+        plan
+    }
+
+    #[test]
+    fn blocks_match_samples() {
+        let len = 10 * BLOCK_SIZE + 100;
+        let samples: Vec<f32> = chain(None).take(len).collect();
+
+        let mut blocks = vec![0f32; len];
+        let mut stream = chain(None);
+        for block in blocks.chunks_mut(100) {
+            assert_eq!(stream.process(block), block.len());
+        }
+        assert_eq!(samples, blocks);
+
+        let mut plan = planned_chain();
+        let planned: Vec<f32> = (0..len).map(|_| plan.next_frame().unwrap()[0]).collect();
+        assert_eq!(samples, planned);
+    }
+}
@@ -0,0 +1,1705 @@
+use std::{borrow::Cow, collections::{HashMap, HashSet}, sync::Arc};
+
+use egui_node_graph2::*;
+use crate::fm;
+use crate::node::{Port, Registry};
+
+// ========= First, define your user data types =============
+
+// Wave struct describing sine waves and stuff
+
+
+/// The NodeData holds a custom data struct inside each node. It's useful to
+/// store additional information that doesn't live in parameters. For this
+/// example, the node data stores the template (i.e. the "type") of the node.
+#[cfg_attr(feature = "persistence", derive(serde::Serialize, serde::Deserialize))]
+pub struct MyNodeData {
+    pub(crate) template: fm::Stream,
+}
+
+/// `DataType`s are what defines the possible range of connections when
+/// attaching two ports together. The graph UI will make sure to not allow
+/// attaching incompatible datatypes.
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "persistence", derive(serde::Serialize, serde::Deserialize))]
+pub enum MyDataType {
+    Stream,
+    Const,
+    Wavetable,
+    Sample,
+    Choice,
+    Partials,
+    Curve,
+    Text,
+}
+
+/// In the graph, input parameters can optionally have a constant value. This
+/// value can be directly edited in a widget inside the node itself.
+///
+/// There will usually be a correspondence between DataTypes and ValueTypes. But
+/// this library makes no attempt to check this consistency. For instance, it is
+/// up to the user code in this example to make sure no parameter is created
+/// with a DataType of Scalar and a ValueType of Vec2.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "persistence", derive(serde::Serialize, serde::Deserialize))]
+pub enum MyValueType {
+    Stream { value: fm::Stream },
+    Const  { value: f32 },
+    Wavetable {
+        path: String,
+        #[cfg_attr(feature = "persistence", serde(skip))]
+        bank: Arc<fm::WavetableBank>,
+    },
+    Sample {
+        path: String,
+        #[cfg_attr(feature = "persistence", serde(skip))]
+        sample: Arc<fm::SampleBuffer>,
+    },
+    Choice { value: usize, options: Vec<String> },
+    Partials { value: Vec<fm::Partial> },
+    Curve { value: Vec<f32> },
+    Text { value: String },
+}
+
+impl Default for MyValueType {
+    fn default() -> Self {
+        // NOTE: This is just a dummy `Default` implementation. The library
+        // requires it to circumvent some internal borrow checker issues.
+        Self::Stream { value: fm::Stream::Empty(fm::Empty::new()) }
+    }
+}
+
+impl MyValueType {
+    /// Tries to downcast this value type to a vector
+    pub fn try_to_stream(self) -> anyhow::Result<fm::Stream> {
+        if let MyValueType::Stream { value } = self {
+            Ok(value)
+        } else {
+            anyhow::bail!("Invalid cast from {:?} to vec2", self)
+        }
+    }
+
+    /// Tries to downcast this value type to a scalar
+    pub fn try_to_const(self) -> anyhow::Result<f32> {
+        if let MyValueType::Const { value } = self {
+            Ok(value)
+        } else {
+            anyhow::bail!("Invalid cast from {:?} to scalar", self)
+        }
+    }
+
+    /// Tries to downcast this value type to a wavetable bank
+    pub fn try_to_wavetable(self) -> anyhow::Result<Arc<fm::WavetableBank>> {
+        if let MyValueType::Wavetable { bank, .. } = self {
+            Ok(bank)
+        } else {
+            anyhow::bail!("Invalid cast from {:?} to wavetable", self)
+        }
+    }
+
+    /// Tries to downcast this value type to a sample buffer
+    pub fn try_to_sample(self) -> anyhow::Result<Arc<fm::SampleBuffer>> {
+        if let MyValueType::Sample { sample, .. } = self {
+            Ok(sample)
+        } else {
+            anyhow::bail!("Invalid cast from {:?} to sample", self)
+        }
+    }
+
+    /// Tries to downcast this value type to the index of a choice
+    pub fn try_to_choice(self) -> anyhow::Result<usize> {
+        if let MyValueType::Choice { value, .. } = self {
+            Ok(value)
+        } else {
+            anyhow::bail!("Invalid cast from {:?} to choice", self)
+        }
+    }
+
+    /// Tries to downcast this value type to a list of partials
+    pub fn try_to_partials(self) -> anyhow::Result<Vec<fm::Partial>> {
+        if let MyValueType::Partials { value } = self {
+            Ok(value)
+        } else {
+            anyhow::bail!("Invalid cast from {:?} to partials", self)
+        }
+    }
+
+    /// Tries to downcast this value type to the points of a drawn curve
+    pub fn try_to_curve(self) -> anyhow::Result<Vec<f32>> {
+        if let MyValueType::Curve { value } = self {
+            Ok(value)
+        } else {
+            anyhow::bail!("Invalid cast from {:?} to curve", self)
+        }
+    }
+
+    /// Tries to downcast this value type to a string
+    pub fn try_to_text(self) -> anyhow::Result<String> {
+        if let MyValueType::Text { value } = self {
+            Ok(value)
+        } else {
+            anyhow::bail!("Invalid cast from {:?} to text", self)
+        }
+    }
+}
+
+/// The graph 'global' state. This state struct is passed around to the node and
+/// parameter drawing callbacks. The contents of this struct are entirely up to
+/// the user. For this example, we use it to keep track of the 'active' node.
+#[derive(Default)]
+#[cfg_attr(feature = "persistence", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+pub struct MyGraphState {
+    pub active_node: Option<NodeId>,
+    pub muted: HashSet<NodeId>,
+    pub soloed: HashSet<NodeId>,
+    /// Nodes left out of the plan playing, nothing heard depends on them.
+    pub unreachable: HashSet<NodeId>,
+    /// The Note node whose play button is held down.
+    pub held_note: Option<NodeId>,
+}
+
+impl MyGraphState {
+    /// An output is heard when it is soloed, or when nothing is soloed and it
+    /// isn't muted.
+    pub fn is_audible(&self, node: NodeId) -> bool {
+        if self.soloed.is_empty() {
+            !self.muted.contains(&node)
+        } else {
+            self.soloed.contains(&node)
+        }
+    }
+}
+
+// A trait for the node kinds, which tells the library how to build new nodes
+// from the templates in the node finder
+impl NodeTemplateTrait for fm::Stream {
+    type NodeData = MyNodeData;
+    type DataType = MyDataType;
+    type ValueType = MyValueType;
+    type UserState = MyGraphState;
+    type CategoryType = &'static str;
+
+    fn node_finder_label(&self, _user_state: &mut Self::UserState) -> Cow<'_, str> {
+        Cow::Borrowed(match self {
+            Self::SineWave(_) => "Sine Wave",
+            Self::SquareWave(_) => "Square Wave",
+            Self::TriangleWave(_) => "Triangle Wave",
+            Self::SawtoothWave(_) => "Sawtooth",
+            Self::Wavetable(_) => "Wavetable",
+            Self::Sampler(_) => "Sampler",
+            Self::Pluck(_) => "Plucked String",
+            Self::Additive(_) => "Additive",
+            Self::Supersaw(_) => "Supersaw",
+            Self::Granular(_) => "Granular",
+            Self::Biquad(_) => "Biquad Filter",
+            Self::StateVariableFilter(_) => "State Variable Filter",
+            Self::LadderFilter(_) => "Ladder Filter",
+            Self::Delay(_) => "Delay",
+            Self::Reverb(_) => "Reverb",
+            Self::Chorus(_) => "Chorus",
+            Self::Flanger(_) => "Flanger",
+            Self::Phaser(_) => "Phaser",
+            Self::Distortion(_) => "Distortion",
+            Self::Bitcrusher(_) => "Bitcrusher",
+            Self::Compressor(_) => "Compressor",
+            Self::Limiter(_) => "Limiter",
+            Self::Pan(_) => "Pan",
+            Self::Width(_) => "Stereo Width",
+            Self::Output(_) => "Output",
+            Self::ModulatedSineWave(_) => "Modulator",
+            Self::Mix(_) => "Mix",
+            Self::Empty(_) => "Empty",
+            Self::Envelope(_) => "Envelope",
+            Self::Perlin (_) => "Perlin Noise",
+            Self::WhiteNoise (_) => "White Noise",
+            Self::Parameter(_) => "Parameter",
+            Self::Note(_) => "Note",
+            Self::Custom(node) => node.node().name(),
+            Self::Bus(_) => "Bus",
+            Self::Const(_) => "Const",
+        })
+    }
+
+    // this is what allows the library to show collapsible lists in the node finder.
+    fn node_finder_categories(&self, _user_state: &mut Self::UserState) -> Vec<&'static str> {
+        match self {
+            Self::SineWave(_) => vec!["Waves"],
+            Self::SquareWave(_) => vec!["Waves"],
+            Self::TriangleWave(_) => vec!["Waves"],
+            Self::SawtoothWave(_) => vec!["Waves"],
+            Self::Wavetable(_) => vec!["Waves"],
+            Self::Sampler(_) => vec!["Samples"],
+            Self::Pluck(_) => vec!["Waves"],
+            Self::Additive(_) => vec!["Waves"],
+            Self::Supersaw(_) => vec!["Waves"],
+            Self::Granular(_) => vec!["Samples"],
+            Self::Biquad(_) => vec!["Filters"],
+            Self::StateVariableFilter(_) => vec!["Filters"],
+            Self::LadderFilter(_) => vec!["Filters"],
+            Self::Delay(_) => vec!["Effects"],
+            Self::Reverb(_) => vec!["Effects"],
+            Self::Chorus(_) => vec!["Effects"],
+            Self::Flanger(_) => vec!["Effects"],
+            Self::Phaser(_) => vec!["Effects"],
+            Self::Distortion(_) => vec!["Effects"],
+            Self::Bitcrusher(_) => vec!["Effects"],
+            Self::Compressor(_) => vec!["Dynamics"],
+            Self::Limiter(_) => vec!["Dynamics"],
+            Self::Pan(_) => vec!["Stereo"],
+            Self::Width(_) => vec!["Stereo"],
+            Self::Output(_) => vec!["Outputs"],
+            Self::ModulatedSineWave(_) => vec![],
+            Self::Mix(_) => vec![],
+            Self::Empty(_) => vec![],
+            Self::Envelope(_) => vec![],
+            Self::Perlin (_) => vec!["Noise"],
+            Self::WhiteNoise (_) => vec!["Noise"],
+            Self::Parameter(_) => vec!["Controls"],
+            Self::Note(_) => vec!["Controls"],
+            Self::Custom(node) => vec![node.node().category()],
+            Self::Bus(_) => vec![],
+            Self::Const(_) => vec!["Math"],
+        }
+    }
+
+    fn node_graph_label(&self, user_state: &mut Self::UserState) -> String {
+        // It's okay to delegate this to node_finder_label if you don't want to
+        // show different names in the node finder and the node itself.
+        self.node_finder_label(user_state).into()
+    }
+
+    fn user_data(&self, _user_state: &mut Self::UserState) -> Self::NodeData {
+        MyNodeData { template: self.clone() }
+    }
+
+    fn build_node(
+        &self,
+        graph: &mut Graph<Self::NodeData, Self::DataType, Self::ValueType>,
+        _user_state: &mut Self::UserState,
+        node_id: NodeId,
+    ) {
+        // The nodes are created empty by default. This function needs to take
+        // care of creating the desired inputs and outputs based on the template
+        let add_const_param = |graph: &mut Graph<MyNodeData, MyDataType, MyValueType>, name: &str, value: f32| {
+            graph.add_input_param(
+                node_id,
+                name.into(),
+                MyDataType::Const,
+                MyValueType::Const { value },
+                InputParamKind::ConnectionOrConstant,
+                true,
+            );
+        };
+        // Modulatable parameters are streams that fall back to a constant
+        // while nothing is connected to them.
+        let add_modulated_param = |graph: &mut Graph<MyNodeData, MyDataType, MyValueType>, name: &str, value: f32| {
+            let mut constant = fm::Const::new();
+            constant.set_val(value);
+            graph.add_input_param(
+                node_id,
+                name.into(),
+                MyDataType::Stream,
+                MyValueType::Stream { value: fm::Stream::Const(constant) },
+                InputParamKind::ConnectionOrConstant,
+                true,
+            );
+        };
+        let add_choice_param = |graph: &mut Graph<MyNodeData, MyDataType, MyValueType>, name: &str, options: &[&str], value: usize| {
+            graph.add_input_param(
+                node_id,
+                name.into(),
+                MyDataType::Choice,
+                MyValueType::Choice { value, options: options.iter().map(|o| o.to_string()).collect() },
+                InputParamKind::ConstantOnly,
+                true,
+            );
+        };
+        match self {
+            Self::SineWave(_) => {
+                // The first input param doesn't use the closure so we can comment
+                // it in more detail.
+                graph.add_input_param(
+                    node_id,
+                    // This is the name of the parameter. Can be later used to
+                    // retrieve the value. Parameter names should be unique.
+                    "Frequency".into(),
+                    // The data type for this input. In this case, a scalar
+                    MyDataType::Const,
+                    // The value type for this input. We store zero as default
+                    MyValueType::Const { value: 440.0 }, 
+                    // The input parameter kind. This allows defining whether a
+                    // parameter accepts input connections and/or an inline
+                    // widget to set its value.
+                    InputParamKind::ConnectionOrConstant,
+                    true,
+                );
+
+                graph.add_input_param(
+                    node_id,
+                    "Phase Shift".into(),
+                    MyDataType::Const,
+                    MyValueType::Const { value: 0.0 },
+                    InputParamKind::ConnectionOrConstant,
+                    true,
+                );
+
+                graph.add_input_param(
+                    node_id,
+                    "Sync".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+
+                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
+            }
+            Self::SquareWave(_) => {
+                // The first input param doesn't use the closure so we can comment
+                // it in more detail.
+                graph.add_input_param(
+                    node_id,
+                    // This is the name of the parameter. Can be later used to
+                    // retrieve the value. Parameter names should be unique.
+                    "Frequency".into(),
+                    // The data type for this input. In this case, a scalar
+                    MyDataType::Const,
+                    // The value type for this input. We store zero as default
+                    MyValueType::Const { value: 440.0 }, 
+                    // The input parameter kind. This allows defining whether a
+                    // parameter accepts input connections and/or an inline
+                    // widget to set its value.
+                    InputParamKind::ConnectionOrConstant,
+                    true,
+                );
+
+                graph.add_input_param(
+                    node_id,
+                    "Phase Shift".into(),
+                    MyDataType::Const,
+                    MyValueType::Const { value: 0.0 },
+                    InputParamKind::ConnectionOrConstant,
+                    true,
+                );
+
+                graph.add_input_param(
+                    node_id,
+                    "Sync".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+
+                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
+            }
+            Self::TriangleWave(_) => {
+                // The first input param doesn't use the closure so we can comment
+                // it in more detail.
+                graph.add_input_param(
+                    node_id,
+                    // This is the name of the parameter. Can be later used to
+                    // retrieve the value. Parameter names should be unique.
+                    "Frequency".into(),
+                    // The data type for this input. In this case, a scalar
+                    MyDataType::Const,
+                    // The value type for this input. We store zero as default
+                    MyValueType::Const { value: 440.0 }, 
+                    // The input parameter kind. This allows defining whether a
+                    // parameter accepts input connections and/or an inline
+                    // widget to set its value.
+                    InputParamKind::ConnectionOrConstant,
+                    true,
+                );
+
+                graph.add_input_param(
+                    node_id,
+                    "Phase Shift".into(),
+                    MyDataType::Const,
+                    MyValueType::Const { value: 0.0 },
+                    InputParamKind::ConnectionOrConstant,
+                    true,
+                );
+
+                graph.add_input_param(
+                    node_id,
+                    "Sync".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+
+                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
+            }
+            Self::SawtoothWave(_) => {
+                // The first input param doesn't use the closure so we can comment
+                // it in more detail.
+                graph.add_input_param(
+                    node_id,
+                    // This is the name of the parameter. Can be later used to
+                    // retrieve the value. Parameter names should be unique.
+                    "Frequency".into(),
+                    // The data type for this input. In this case, a scalar
+                    MyDataType::Const,
+                    // The value type for this input. We store zero as default
+                    MyValueType::Const { value: 440.0 }, 
+                    // The input parameter kind. This allows defining whether a
+                    // parameter accepts input connections and/or an inline
+                    // widget to set its value.
+                    InputParamKind::ConnectionOrConstant,
+                    true,
+                );
+
+                graph.add_input_param(
+                    node_id,
+                    "Phase Shift".into(),
+                    MyDataType::Const,
+                    MyValueType::Const { value: 0.0 },
+                    InputParamKind::ConnectionOrConstant,
+                    true,
+                );
+
+                graph.add_input_param(
+                    node_id,
+                    "Sync".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+
+                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
+            }
+            Self::Wavetable(_) => {
+                graph.add_input_param(
+                    node_id,
+                    "Table".into(),
+                    MyDataType::Wavetable,
+                    MyValueType::Wavetable { path: String::new(), bank: Arc::new(fm::WavetableBank::shapes()) },
+                    InputParamKind::ConstantOnly,
+                    true,
+                );
+
+                add_const_param(graph, "Frequency", 440.0);
+                add_modulated_param(graph, "Position", 0.0);
+
+                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
+            }
+            Self::Sampler(_) => {
+                graph.add_input_param(
+                    node_id,
+                    "Sample".into(),
+                    MyDataType::Sample,
+                    MyValueType::Sample { path: String::new(), sample: Arc::default() },
+                    InputParamKind::ConstantOnly,
+                    true,
+                );
+
+                add_modulated_param(graph, "Rate", 1.0);
+                add_const_param(graph, "Pitch", 0.0);
+                add_const_param(graph, "Start", 0.0);
+                add_const_param(graph, "End", 1.0);
+                add_choice_param(graph, "Loop Mode", &fm::LoopMode::NAMES, 0);
+
+                graph.add_input_param(
+                    node_id,
+                    "Gate".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+
+                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
+            }
+            Self::Pluck(_) => {
+                add_const_param(graph, "Frequency", 220.0);
+                add_const_param(graph, "Damping", 0.3);
+                add_const_param(graph, "Brightness", 0.7);
+
+                graph.add_input_param(
+                    node_id,
+                    "Trigger".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+
+                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
+            }
+            Self::Additive(_) => {
+                add_const_param(graph, "Frequency", 220.0);
+                add_choice_param(graph, "Preset", &fm::AdditivePreset::NAMES, 0);
+                add_const_param(graph, "Harmonics", 16.0);
+
+                graph.add_input_param(
+                    node_id,
+                    "Partials".into(),
+                    MyDataType::Partials,
+                    MyValueType::Partials { value: vec![
+                        fm::Partial::new(1.0, 1.0),
+                        fm::Partial::new(2.0, 0.5),
+                        fm::Partial::new(3.0, 0.25),
+                    ] },
+                    InputParamKind::ConstantOnly,
+                    true,
+                );
+
+                add_const_param(graph, "Tilt", 0.0);
+
+                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
+            }
+            Self::Supersaw(_) => {
+                add_const_param(graph, "Frequency", 220.0);
+                add_const_param(graph, "Voices", 7.0);
+                add_const_param(graph, "Detune", 25.0);
+                add_const_param(graph, "Spread", 0.5);
+
+                for name in fm::Channel::NAMES {
+                    graph.add_output_param(node_id, name.into(), MyDataType::Stream);
+                }
+            }
+            Self::Granular(_) => {
+                graph.add_input_param(
+                    node_id,
+                    "Stream".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+
+                graph.add_input_param(
+                    node_id,
+                    "Sample".into(),
+                    MyDataType::Sample,
+                    MyValueType::Sample { path: String::new(), sample: Arc::default() },
+                    InputParamKind::ConstantOnly,
+                    true,
+                );
+
+                add_choice_param(graph, "Source", &fm::GrainSource::NAMES, 0);
+                add_const_param(graph, "Grain Size", 80.0);
+                add_const_param(graph, "Density", 20.0);
+                add_modulated_param(graph, "Position", 0.2);
+                add_const_param(graph, "Position Jitter", 0.05);
+                add_const_param(graph, "Pitch", 0.0);
+                add_const_param(graph, "Pitch Jitter", 0.0);
+
+                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
+            }
+            Self::Biquad(_) => {
+                graph.add_input_param(
+                    node_id,
+                    "Stream".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+
+                add_choice_param(graph, "Mode", &fm::FilterMode::NAMES, 0);
+                add_modulated_param(graph, "Cutoff", 1000.0);
+                add_modulated_param(graph, "Q", 0.707);
+                add_const_param(graph, "Gain", 0.0);
+
+                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
+            }
+            Self::StateVariableFilter(_) => {
+                graph.add_input_param(
+                    node_id,
+                    "Stream".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+
+                add_modulated_param(graph, "Cutoff", 1000.0);
+                add_modulated_param(graph, "Q", 0.707);
+
+                // One output per response, all computed by the same filter
+                for name in fm::SvfOutput::NAMES {
+                    graph.add_output_param(node_id, name.into(), MyDataType::Stream);
+                }
+            }
+            Self::LadderFilter(_) => {
+                graph.add_input_param(
+                    node_id,
+                    "Stream".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+
+                add_modulated_param(graph, "Cutoff", 1000.0);
+                add_modulated_param(graph, "Resonance", 0.3);
+                add_const_param(graph, "Drive", 1.0);
+
+                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
+            }
+            Self::Delay(_) => {
+                graph.add_input_param(
+                    node_id,
+                    "Stream".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+
+                add_modulated_param(graph, "Time", 300.0);
+                add_const_param(graph, "Feedback", 0.4);
+                add_const_param(graph, "Mix", 0.35);
+                add_const_param(graph, "Feedback Cutoff", 4000.0);
+                add_choice_param(graph, "Sync", &fm::TempoSync::NAMES, 0);
+                add_const_param(graph, "BPM", 120.0);
+                add_choice_param(graph, "Ping-Pong", &["Off", "On"], 0);
+
+                for name in fm::Channel::NAMES {
+                    graph.add_output_param(node_id, name.into(), MyDataType::Stream);
+                }
+            }
+            Self::Reverb(_) => {
+                graph.add_input_param(
+                    node_id,
+                    "Stream".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+
+                add_const_param(graph, "Room Size", 0.7);
+                add_const_param(graph, "Damping", 0.5);
+                add_const_param(graph, "Pre-Delay", 20.0);
+                add_const_param(graph, "Mix", 0.3);
+
+                for name in fm::Channel::NAMES {
+                    graph.add_output_param(node_id, name.into(), MyDataType::Stream);
+                }
+            }
+            Self::Chorus(_) => {
+                graph.add_input_param(
+                    node_id,
+                    "Stream".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+
+                add_const_param(graph, "Rate", 0.8);
+                add_const_param(graph, "Depth", 0.5);
+                add_const_param(graph, "Feedback", 0.0);
+                add_const_param(graph, "Mix", 0.5);
+
+                // Replaces the internal sine LFO when connected.
+                graph.add_input_param(
+                    node_id,
+                    "LFO".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+
+                for name in fm::Channel::NAMES {
+                    graph.add_output_param(node_id, name.into(), MyDataType::Stream);
+                }
+            }
+            Self::Flanger(_) => {
+                graph.add_input_param(
+                    node_id,
+                    "Stream".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+
+                add_const_param(graph, "Rate", 0.25);
+                add_const_param(graph, "Depth", 0.5);
+                add_const_param(graph, "Feedback", 0.6);
+                add_const_param(graph, "Mix", 0.5);
+
+                // Replaces the internal sine LFO when connected.
+                graph.add_input_param(
+                    node_id,
+                    "LFO".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+
+                for name in fm::Channel::NAMES {
+                    graph.add_output_param(node_id, name.into(), MyDataType::Stream);
+                }
+            }
+            Self::Phaser(_) => {
+                graph.add_input_param(
+                    node_id,
+                    "Stream".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+
+                add_const_param(graph, "Rate", 0.5);
+                add_const_param(graph, "Depth", 0.7);
+                add_const_param(graph, "Feedback", 0.4);
+                add_const_param(graph, "Mix", 0.5);
+
+                // Replaces the internal sine LFO when connected.
+                graph.add_input_param(
+                    node_id,
+                    "LFO".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+
+                for name in fm::Channel::NAMES {
+                    graph.add_output_param(node_id, name.into(), MyDataType::Stream);
+                }
+            }
+            Self::Distortion(_) => {
+                graph.add_input_param(
+                    node_id,
+                    "Stream".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+
+                add_modulated_param(graph, "Drive", 2.0);
+                add_choice_param(graph, "Curve", &fm::ShaperCurve::NAMES, 0);
+                add_const_param(graph, "Order", 3.0);
+
+                graph.add_input_param(
+                    node_id,
+                    "Shape".into(),
+                    MyDataType::Curve,
+                    MyValueType::Curve { value: fm::identity_curve() },
+                    InputParamKind::ConstantOnly,
+                    true,
+                );
+
+                add_choice_param(graph, "Oversampling", &["1x", "2x", "4x"], 0);
+
+                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
+            }
+            Self::Bitcrusher(_) => {
+                graph.add_input_param(
+                    node_id,
+                    "Stream".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+
+                add_const_param(graph, "Bits", 8.0);
+                add_modulated_param(graph, "Rate", 11025.0);
+                add_choice_param(graph, "Dither", &["Off", "TPDF"], 0);
+
+                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
+            }
+            Self::Compressor(_) => {
+                graph.add_input_param(
+                    node_id,
+                    "Stream".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+
+                add_const_param(graph, "Threshold", -18.0);
+                add_const_param(graph, "Ratio", 4.0);
+                add_const_param(graph, "Attack", 10.0);
+                add_const_param(graph, "Release", 100.0);
+                add_const_param(graph, "Knee", 6.0);
+                add_const_param(graph, "Makeup", 0.0);
+
+                // Drives the gain reduction instead of the input when connected.
+                graph.add_input_param(
+                    node_id,
+                    "Sidechain".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+
+                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
+            }
+            Self::Limiter(_) => {
+                graph.add_input_param(
+                    node_id,
+                    "Stream".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+
+                add_const_param(graph, "Threshold", -1.0);
+                add_const_param(graph, "Lookahead", 5.0);
+                add_const_param(graph, "Release", 100.0);
+                add_const_param(graph, "Makeup", 0.0);
+
+                // Drives the gain reduction instead of the input when connected.
+                graph.add_input_param(
+                    node_id,
+                    "Sidechain".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+
+                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
+            }
+            Self::Pan(_) => {
+                graph.add_input_param(
+                    node_id,
+                    "Stream".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+
+                add_modulated_param(graph, "Pan", 0.0);
+
+                for name in fm::Channel::NAMES {
+                    graph.add_output_param(node_id, name.into(), MyDataType::Stream);
+                }
+            }
+            Self::Width(_) => {
+                graph.add_input_param(
+                    node_id,
+                    "Left".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+                graph.add_input_param(
+                    node_id,
+                    "Right".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+
+                add_const_param(graph, "Width", 1.0);
+
+                for name in fm::Channel::NAMES {
+                    graph.add_output_param(node_id, name.into(), MyDataType::Stream);
+                }
+            }
+            Self::Output(_) => {
+                graph.add_input_param(
+                    node_id,
+                    "Name".into(),
+                    MyDataType::Text,
+                    MyValueType::Text { value: "output".into() },
+                    InputParamKind::ConstantOnly,
+                    true,
+                );
+
+                graph.add_input_param(
+                    node_id,
+                    "Left".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+
+                // Copies the left side when left unconnected
+                graph.add_input_param(
+                    node_id,
+                    "Right".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+            }
+            Self::ModulatedSineWave(_) => {
+                graph.add_input_param(
+                    node_id,
+                    "Frequency".into(),
+                    MyDataType::Const,
+                    MyValueType::Const { value: 0.0 },
+                    InputParamKind::ConnectionOrConstant,
+                    true,
+                );
+
+                graph.add_input_param(
+                    node_id,
+                    "Modulation".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+
+                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
+            }
+            Self::Mix(_) => {
+                graph.add_input_param(
+                    node_id,
+                    "p".into(),
+                    MyDataType::Const,
+                    MyValueType::Const { value: 0.5 },
+                    InputParamKind::ConnectionOrConstant,
+                    true,
+                );   
+
+                graph.add_input_param(
+                    node_id,
+                    "A".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );                 
+                
+                graph.add_input_param(
+                    node_id,
+                    "B".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::Empty(fm::Empty::new()) },
+                    InputParamKind::ConnectionOnly,
+                    true,
+                );
+
+                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
+            }
+            Self::Const(_) => {
+                graph.add_input_param(
+                    node_id,
+                    "Value".into(),
+                    MyDataType::Const,
+                    MyValueType::Const { value: 0.5 },
+                    InputParamKind::ConnectionOrConstant,
+                    true,
+                );
+
+                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream); 
+            },
+            Self::Empty(_) => { graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream); },
+            Self::Envelope(_) => {
+                graph.add_input_param(
+                    node_id,
+                    "Stream".into(),
+                    MyDataType::Stream,
+                    MyValueType::Stream { value: fm::Stream::default() }, 
+                    InputParamKind::ConnectionOrConstant,
+                    true,
+                );
+
+                add_const_param(graph, "Attack Duration", 0.3);
+                add_const_param(graph, "Attack Amplitude", 1.0);
+                add_const_param(graph, "Decay Duration", 0.3);
+                add_const_param(graph, "Sustain Duration", 2.0);
+                add_const_param(graph, "Sustain Amplitude", 0.6);
+                add_const_param(graph, "Release Duration", 1.0);
+
+                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
+            }
+            Self::WhiteNoise(_) => {
+                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
+            }
+            // Plays its value until a host sets the parameter by name.
+            Self::Parameter(_) => {
+                graph.add_input_param(
+                    node_id,
+                    "Name".into(),
+                    MyDataType::Text,
+                    MyValueType::Text { value: "param".into() },
+                    InputParamKind::ConstantOnly,
+                    true,
+                );
+                add_const_param(graph, "Value", 0.0);
+                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
+            }
+            Self::Note(_) => {
+                graph.add_output_param(node_id, "Gate".into(), MyDataType::Stream);
+                graph.add_output_param(node_id, "Pitch".into(), MyDataType::Stream);
+                graph.add_output_param(node_id, "Velocity".into(), MyDataType::Stream);
+            }
+            Self::Perlin(_) => {
+                graph.add_input_param(
+                    node_id,
+                    "Scale".into(),
+                    MyDataType::Const,
+                    MyValueType::Const { value: 1.0 }, 
+                    InputParamKind::ConnectionOrConstant,
+                    true,
+                );
+                graph.add_output_param(node_id, "Stream".into(), MyDataType::Stream);
+            }
+            Self::Custom(node) => {
+                for port in node.node().ports() {
+                    match port {
+                        // Silent until something is connected
+                        Port::Stream(name) => {
+                            graph.add_input_param(
+                                node_id,
+                                name.into(),
+                                MyDataType::Stream,
+                                MyValueType::Stream { value: fm::Stream::Const(fm::Const::new()) },
+                                InputParamKind::ConnectionOnly,
+                                true,
+                            );
+                        }
+                        Port::Param { name, default, .. } => add_const_param(graph, name, default),
+                    }
+                }
+                for name in node.node().outputs() {
+                    graph.add_output_param(node_id, name.into(), MyDataType::Stream);
+                }
+            }
+            // Only made by plans, never offered in the node finder.
+            Self::Bus(_) => {}
+        }
+    }
+}
+
+// The built-in nodes, followed by the ones in the registry.
+pub struct AllMyNodeTemplates<'a> {
+    pub(crate) registry: &'a Registry,
+}
+
+impl NodeTemplateIter for AllMyNodeTemplates<'_> {
+    type Item = fm::Stream;
+
+    fn all_kinds(&self) -> Vec<Self::Item> {
+        // This function must return a list of node kinds, which the node finder
+        // will use to display it to the user. Crates like strum can reduce the
+        // boilerplate in enumerating all variants of an enum.
+        let mut kinds = vec![
+            fm::Stream::SineWave(fm::SineWave::new()),
+            fm::Stream::SquareWave(fm::SquareWave::new()),
+            fm::Stream::TriangleWave(fm::TriangleWave::new()),
+            fm::Stream::SawtoothWave(fm::SawtoothWave::new()),
+            fm::Stream::Wavetable(fm::Wavetable::new()),
+            fm::Stream::Sampler(fm::Sampler::new()),
+            fm::Stream::Pluck(fm::Pluck::new()),
+            fm::Stream::Additive(fm::Additive::new()),
+            fm::Stream::Supersaw(fm::Supersaw::new()),
+            fm::Stream::Granular(fm::Granular::new()),
+            fm::Stream::Biquad(fm::Biquad::new()),
+            fm::Stream::StateVariableFilter(fm::StateVariableFilter::new()),
+            fm::Stream::LadderFilter(fm::LadderFilter::new()),
+            fm::Stream::Delay(fm::Delay::new()),
+            fm::Stream::Reverb(fm::Reverb::new()),
+            fm::Stream::Chorus(fm::Chorus::new()),
+            fm::Stream::Flanger(fm::Flanger::new()),
+            fm::Stream::Phaser(fm::Phaser::new()),
+            fm::Stream::Distortion(fm::Distortion::new()),
+            fm::Stream::Bitcrusher(fm::Bitcrusher::new()),
+            fm::Stream::Compressor(fm::Compressor::new()),
+            fm::Stream::Limiter(fm::Limiter::new()),
+            fm::Stream::Pan(fm::Pan::new()),
+            fm::Stream::Width(fm::Width::new()),
+            fm::Stream::Output(fm::Output::new()),
+            fm::Stream::ModulatedSineWave(fm::ModulatedSineWave::new()),
+            fm::Stream::Mix(fm::Mix::new()),
+            fm::Stream::Const(fm::Const::new()),
+            fm::Stream::Empty(fm::Empty::new()),
+            fm::Stream::Envelope(fm::Envelope::new()),
+            fm::Stream::Perlin(fm::Perlin::new()),
+            fm::Stream::WhiteNoise(fm::WhiteNoise::new()),
+            fm::Stream::Parameter(fm::Parameter::new()),
+            fm::Stream::Note(fm::Note::new()),
+        ];
+        kinds.extend(self.registry.nodes().map(|node| fm::Stream::Custom(fm::Custom::new(node.box_clone()))));
+        kinds
+    }
+}
+
+pub type MyGraph = Graph<MyNodeData, MyDataType, MyValueType>;
+
+// The template the node finder lists under `kind`, like "Sine Wave".
+pub(crate) fn template_named(registry: &Registry, user_state: &mut MyGraphState, kind: &str) -> anyhow::Result<fm::Stream> {
+    AllMyNodeTemplates { registry }.all_kinds().into_iter()
+        .find(|template| template.node_finder_label(user_state) == kind)
+        .ok_or_else(|| anyhow::anyhow!("No node is called {}", kind))
+}
+
+// Adds a node the way the node finder does, with its inputs at their defaults.
+pub(crate) fn add_template(graph: &mut MyGraph, user_state: &mut MyGraphState, template: fm::Stream) -> NodeId {
+    let label = template.node_graph_label(user_state);
+    let user_data = template.user_data(user_state);
+    graph.add_node(label, user_data, |graph, node_id| template.build_node(graph, user_state, node_id))
+}
+
+// The output nodes that can be heard with the current solo and mute state.
+pub(crate) fn audible_outputs(graph: &MyGraph, user_state: &MyGraphState) -> Vec<NodeId> {
+    graph.nodes.iter()
+        .filter(|(node_id, node)| matches!(node.user_data.template, fm::Stream::Output(_)) && user_state.is_audible(*node_id))
+        .map(|(node_id, _)| node_id)
+        .collect()
+}
+
+// The graph compiled so far. Evaluating a node adds a step to the plan for
+// each of its outputs, after the steps of everything it depends on, and the
+// cache keeps the bus reading each output so a node feeding several inputs
+// only runs once.
+pub(crate) struct OutputsCache {
+    pub(crate) plan: fm::Plan,
+    // What the plan's Parameter and Note nodes read.
+    pub(crate) controls: fm::Controls,
+    pub(crate) values: HashMap<OutputId, MyValueType>,
+    evaluated: HashSet<NodeId>,
+    pending: HashSet<NodeId>,
+}
+
+impl OutputsCache {
+    pub(crate) fn new(sample_rate: u32) -> Self {
+        Self {
+            plan: fm::Plan::new(sample_rate),
+            controls: fm::Controls::default(),
+            values: HashMap::new(),
+            evaluated: HashSet::new(),
+            pending: HashSet::new(),
+        }
+    }
+
+    // The nodes that none of the plan's steps came from.
+    pub(crate) fn unreachable(&self, graph: &MyGraph) -> HashSet<NodeId> {
+        graph.nodes.keys().filter(|node| !self.evaluated.contains(node)).collect()
+    }
+}
+
+// Compiles the plan playing a node, in stereo when the node is an output or
+// has a Left and a Right output.
+pub(crate) fn plan_for(graph: &MyGraph, node_id: NodeId, sample_rate: u32) -> anyhow::Result<OutputsCache> {
+    let mut compiled = OutputsCache::new(sample_rate);
+    if let fm::Stream::Output(_) = graph[node_id].user_data.template {
+        add_output_node(graph, node_id, &mut compiled)?;
+        return Ok(compiled);
+    }
+    let left = evaluate_node(graph, node_id, &mut compiled)?.try_to_stream()?;
+    let right = match graph[node_id].get_output("Right").ok().and_then(|id| compiled.values.get(&id)) {
+        Some(right) => right.clone().try_to_stream()?,
+        None => left.clone(),
+    };
+    compiled.plan.add_output(left, right);
+    Ok(compiled)
+}
+
+// Evaluates an output node and adds both its sides to the plan's mix,
+// returning the output's name.
+pub(crate) fn add_output_node(graph: &MyGraph, node_id: NodeId, compiled: &mut OutputsCache) -> anyhow::Result<String> {
+    match evaluate_node(graph, node_id, compiled)?.try_to_stream()? {
+        fm::Stream::Output(output) => {
+            let name = output.name().to_string();
+            let (left, right) = output.sides();
+            compiled.plan.add_output(left, right);
+            Ok(name)
+        }
+        _ => anyhow::bail!("{} is not an output node", graph[node_id].label),
+    }
+}
+
+/// Recursively evaluates all dependencies of this node, then evaluates the node itself.
+///
+/// Every stream created along the way runs at the sample rate of the plan
+/// being compiled.
+fn evaluate_node(
+    graph: &MyGraph,
+    node_id: NodeId,
+    outputs_cache: &mut OutputsCache,
+) -> anyhow::Result<MyValueType> {
+    // Reaching a node again before it's done means its inputs loop back
+    // into it, which no order of steps can run.
+    if !outputs_cache.pending.insert(node_id) {
+        anyhow::bail!("{} feeds back into itself", graph[node_id].label);
+    }
+    let value = evaluate_template(graph, node_id, outputs_cache)?;
+    outputs_cache.pending.remove(&node_id);
+    outputs_cache.evaluated.insert(node_id);
+    Ok(value)
+}
+
+fn evaluate_template(
+    graph: &MyGraph,
+    node_id: NodeId,
+    outputs_cache: &mut OutputsCache,
+) -> anyhow::Result<MyValueType> {
+    // To solve a similar problem as creating node types above, we define an
+    // Evaluator as a convenience. It may be overkill for this small example,
+    // but something like this makes the code much more readable when the
+    // number of nodes starts growing.
+
+    struct Evaluator<'a> {
+        graph: &'a MyGraph,
+        outputs_cache: &'a mut OutputsCache,
+        node_id: NodeId,
+    }
+    impl<'a> Evaluator<'a> {
+        fn new(graph: &'a MyGraph, outputs_cache: &'a mut OutputsCache, node_id: NodeId) -> Self {
+            Self {
+                graph,
+                outputs_cache,
+                node_id,
+            }
+        }
+        fn evaluate_input(&mut self, name: &str) -> anyhow::Result<MyValueType> {
+            // Calling `evaluate_input` recursively evaluates other nodes in the
+            // graph until the input value for a paramater has been computed.
+            evaluate_input(self.graph, self.node_id, name, self.outputs_cache)
+        }
+        fn populate_output(
+            &mut self,
+            name: &str,
+            value: MyValueType,
+        ) -> anyhow::Result<MyValueType> {
+            // After computing an output, we don't just return it, but we also
+            // add it to the plan and cache the bus reading it. This ensures
+            // the plan only ever computes an output once.
+            //
+            // The return value of the function is the "final" output of the
+            // node, the thing we want to get from the evaluation. For nodes
+            // with multiple outputs, see `output_streams` below.
+            //
+            // Note that this is just one possible semantic interpretation of
+            // the graphs, you can come up with your own evaluation semantics!
+            populate_output(self.graph, self.outputs_cache, self.node_id, name, value)
+        }
+        fn input_stream(&mut self, name: &str) -> anyhow::Result<fm::Stream> {
+            self.evaluate_input(name)?.try_to_stream()
+        }
+        fn input_const(&mut self, name: &str) -> anyhow::Result<f32> {
+            self.evaluate_input(name)?.try_to_const()
+        }
+        fn input_wavetable(&mut self, name: &str) -> anyhow::Result<Arc<fm::WavetableBank>> {
+            self.evaluate_input(name)?.try_to_wavetable()
+        }
+        fn input_sample(&mut self, name: &str) -> anyhow::Result<Arc<fm::SampleBuffer>> {
+            self.evaluate_input(name)?.try_to_sample()
+        }
+        fn input_choice(&mut self, name: &str) -> anyhow::Result<usize> {
+            self.evaluate_input(name)?.try_to_choice()
+        }
+        fn input_partials(&mut self, name: &str) -> anyhow::Result<Vec<fm::Partial>> {
+            self.evaluate_input(name)?.try_to_partials()
+        }
+        fn input_curve(&mut self, name: &str) -> anyhow::Result<Vec<f32>> {
+            self.evaluate_input(name)?.try_to_curve()
+        }
+        fn input_text(&mut self, name: &str) -> anyhow::Result<String> {
+            self.evaluate_input(name)?.try_to_text()
+        }
+        fn output_stream(&mut self, name: &str, value: fm::Stream) -> anyhow::Result<MyValueType> {
+            self.populate_output(name, MyValueType::Stream { value })
+        }
+        // Populates every output of a node with several of them, returning
+        // the first one as the node's "final" value.
+        //
+        // Each output gets its own copy of the node. Cloned streams play back
+        // the exact same samples, so the copies stay in lockstep as if they
+        // were the same node.
+        fn output_streams(&mut self, outputs: Vec<(&str, fm::Stream)>) -> anyhow::Result<MyValueType> {
+            let mut first = None;
+            for (name, value) in outputs {
+                let value = self.output_stream(name, value)?;
+                first.get_or_insert(value);
+            }
+            first.ok_or_else(|| anyhow::anyhow!("Node has no outputs"))
+        }
+        // Populates every output of a node that writes them all in a single
+        // step of the plan, with a bus reading each of them.
+        fn output_step(&mut self, names: Vec<&str>, value: fm::Stream) -> anyhow::Result<MyValueType> {
+            let buses = self.outputs_cache.plan.add_outputs_step(value, names.len());
+            let mut first = None;
+            for (name, value) in names.into_iter().zip(buses) {
+                let output_id = self.graph[self.node_id].get_output(name)?;
+                let value = MyValueType::Stream { value };
+                self.outputs_cache.values.insert(output_id, value.clone());
+                first.get_or_insert(value);
+            }
+            first.ok_or_else(|| anyhow::anyhow!("Node has no outputs"))
+        }
+        // Populates the Left and Right outputs of a stereo node, with a copy
+        // of the node set to each side.
+        fn output_stereo<T: Clone>(
+            &mut self,
+            node: T,
+            set_channel: fn(&mut T, fm::Channel),
+            variant: fn(T) -> fm::Stream,
+        ) -> anyhow::Result<MyValueType> {
+            let outputs = fm::Channel::ALL.iter().zip(fm::Channel::NAMES).map(|(&channel, name)| {
+                let mut side = node.clone();
+                set_channel(&mut side, channel);
+                (name, variant(side))
+            }).collect();
+            self.output_streams(outputs)
+        }
+    }
+
+    let node = &graph[node_id];
+    let sample_rate = outputs_cache.plan.sample_rate();
+    let mut evaluator = Evaluator::new(graph, outputs_cache, node_id);
+    let mut template = node.user_data.template.clone();
+    template.set_sample_rate(sample_rate);
+    match template {
+        fm::Stream::SineWave(mut wave) => {
+            wave.set_frequency(evaluator.input_const("Frequency")?);
+            wave.set_phase_shift(evaluator.input_const("Phase Shift")?);
+            wave.set_sync(evaluator.input_stream("Sync")?);
+            evaluator.output_stream("Stream", fm::Stream::SineWave(wave))
+        }
+        fm::Stream::SquareWave(mut wave) => {
+            wave.set_frequency(evaluator.input_const("Frequency")?);
+            wave.set_phase_shift(evaluator.input_const("Phase Shift")?);
+            wave.set_sync(evaluator.input_stream("Sync")?);
+            evaluator.output_stream("Stream", fm::Stream::SquareWave(wave))
+        }
+        fm::Stream::TriangleWave(mut wave) => {
+            wave.set_frequency(evaluator.input_const("Frequency")?);
+            wave.set_phase_shift(evaluator.input_const("Phase Shift")?);
+            wave.set_sync(evaluator.input_stream("Sync")?);
+            evaluator.output_stream("Stream", fm::Stream::TriangleWave(wave))
+        }
+        fm::Stream::SawtoothWave(mut wave) => {
+            wave.set_frequency(evaluator.input_const("Frequency")?);
+            wave.set_phase_shift(evaluator.input_const("Phase Shift")?);
+            wave.set_sync(evaluator.input_stream("Sync")?);
+            evaluator.output_stream("Stream", fm::Stream::SawtoothWave(wave))
+        }
+        fm::Stream::Wavetable(mut wave) => {
+            wave.set_bank(evaluator.input_wavetable("Table")?);
+            wave.set_frequency(evaluator.input_const("Frequency")?);
+            wave.set_position(evaluator.input_stream("Position")?);
+            evaluator.output_stream("Stream", fm::Stream::Wavetable(wave))
+        }
+        fm::Stream::Sampler(mut wave) => {
+            wave.set_sample(evaluator.input_sample("Sample")?);
+            wave.set_rate(evaluator.input_stream("Rate")?);
+            wave.set_pitch(evaluator.input_const("Pitch")?);
+            wave.set_start(evaluator.input_const("Start")?);
+            wave.set_end(evaluator.input_const("End")?);
+            wave.set_loop_mode(fm::LoopMode::from_index(evaluator.input_choice("Loop Mode")?));
+            wave.set_gate(evaluator.input_stream("Gate")?);
+            evaluator.output_stream("Stream", fm::Stream::Sampler(wave))
+        }
+        fm::Stream::Pluck(mut wave) => {
+            wave.set_frequency(evaluator.input_const("Frequency")?);
+            wave.set_damping(evaluator.input_const("Damping")?);
+            wave.set_brightness(evaluator.input_const("Brightness")?);
+            wave.set_trigger(evaluator.input_stream("Trigger")?);
+            evaluator.output_stream("Stream", fm::Stream::Pluck(wave))
+        }
+        fm::Stream::Additive(mut wave) => {
+            let frequency = evaluator.input_const("Frequency")?;
+            wave.set_frequency(frequency);
+            let preset = fm::AdditivePreset::from_index(evaluator.input_choice("Preset")?);
+            let harmonics = evaluator.input_const("Harmonics")?.max(0.0) as usize;
+            let partials = evaluator.input_partials("Partials")?;
+            // Only the partials below Nyquist are heard
+            let nyquist = evaluator.outputs_cache.plan.sample_rate() as f32 / 2.0;
+            wave.set_partials(match preset {
+                fm::AdditivePreset::Custom => partials,
+                preset => preset.partials(harmonics, nyquist / frequency.abs()),
+            });
+            wave.set_tilt(evaluator.input_const("Tilt")?);
+            evaluator.output_stream("Stream", fm::Stream::Additive(wave))
+        }
+        fm::Stream::Supersaw(mut wave) => {
+            wave.set_frequency(evaluator.input_const("Frequency")?);
+            wave.set_voices(evaluator.input_const("Voices")?.max(1.0) as usize);
+            wave.set_detune(evaluator.input_const("Detune")?);
+            wave.set_spread(evaluator.input_const("Spread")?);
+            evaluator.output_stereo(wave, fm::Supersaw::set_channel, fm::Stream::Supersaw)
+        }
+        fm::Stream::Granular(mut wave) => {
+            wave.set_input(evaluator.input_stream("Stream")?);
+            wave.set_sample(evaluator.input_sample("Sample")?);
+            wave.set_source(fm::GrainSource::from_index(evaluator.input_choice("Source")?));
+            wave.set_grain_size(evaluator.input_const("Grain Size")?);
+            wave.set_density(evaluator.input_const("Density")?);
+            wave.set_position(evaluator.input_stream("Position")?);
+            wave.set_position_jitter(evaluator.input_const("Position Jitter")?);
+            wave.set_pitch(evaluator.input_const("Pitch")?);
+            wave.set_pitch_jitter(evaluator.input_const("Pitch Jitter")?);
+            evaluator.output_stream("Stream", fm::Stream::Granular(wave))
+        }
+        fm::Stream::Biquad(mut wave) => {
+            wave.set_stream(evaluator.input_stream("Stream")?);
+            wave.set_mode(fm::FilterMode::from_index(evaluator.input_choice("Mode")?));
+            wave.set_cutoff(evaluator.input_stream("Cutoff")?);
+            wave.set_q(evaluator.input_stream("Q")?);
+            wave.set_gain(evaluator.input_const("Gain")?);
+            evaluator.output_stream("Stream", fm::Stream::Biquad(wave))
+        }
+        fm::Stream::StateVariableFilter(mut wave) => {
+            wave.set_stream(evaluator.input_stream("Stream")?);
+            wave.set_cutoff(evaluator.input_stream("Cutoff")?);
+            wave.set_q(evaluator.input_stream("Q")?);
+            let outputs = fm::SvfOutput::ALL.iter().zip(fm::SvfOutput::NAMES).map(|(&output, name)| {
+                let mut tap = wave.clone();
+                tap.set_output(output);
+                (name, fm::Stream::StateVariableFilter(tap))
+            }).collect();
+            evaluator.output_streams(outputs)
+        }
+        fm::Stream::LadderFilter(mut wave) => {
+            wave.set_stream(evaluator.input_stream("Stream")?);
+            wave.set_cutoff(evaluator.input_stream("Cutoff")?);
+            wave.set_resonance(evaluator.input_stream("Resonance")?);
+            wave.set_drive(evaluator.input_const("Drive")?);
+            evaluator.output_stream("Stream", fm::Stream::LadderFilter(wave))
+        }
+        fm::Stream::Delay(mut wave) => {
+            wave.set_stream(evaluator.input_stream("Stream")?);
+            wave.set_time(evaluator.input_stream("Time")?);
+            wave.set_feedback(evaluator.input_const("Feedback")?);
+            wave.set_mix(evaluator.input_const("Mix")?);
+            wave.set_feedback_cutoff(evaluator.input_const("Feedback Cutoff")?);
+            wave.set_sync(fm::TempoSync::from_index(evaluator.input_choice("Sync")?));
+            wave.set_bpm(evaluator.input_const("BPM")?);
+            wave.set_ping_pong(evaluator.input_choice("Ping-Pong")? == 1);
+            evaluator.output_stereo(wave, fm::Delay::set_channel, fm::Stream::Delay)
+        }
+        fm::Stream::Reverb(mut wave) => {
+            wave.set_stream(evaluator.input_stream("Stream")?);
+            wave.set_room_size(evaluator.input_const("Room Size")?);
+            wave.set_damping(evaluator.input_const("Damping")?);
+            wave.set_pre_delay(evaluator.input_const("Pre-Delay")?);
+            wave.set_mix(evaluator.input_const("Mix")?);
+            evaluator.output_stereo(wave, fm::Reverb::set_channel, fm::Stream::Reverb)
+        }
+        fm::Stream::Chorus(mut wave) => {
+            wave.set_stream(evaluator.input_stream("Stream")?);
+            wave.set_rate(evaluator.input_const("Rate")?);
+            wave.set_depth(evaluator.input_const("Depth")?);
+            wave.set_feedback(evaluator.input_const("Feedback")?);
+            wave.set_mix(evaluator.input_const("Mix")?);
+            wave.set_lfo(evaluator.input_stream("LFO")?);
+            evaluator.output_stereo(wave, fm::Chorus::set_channel, fm::Stream::Chorus)
+        }
+        fm::Stream::Flanger(mut wave) => {
+            wave.set_stream(evaluator.input_stream("Stream")?);
+            wave.set_rate(evaluator.input_const("Rate")?);
+            wave.set_depth(evaluator.input_const("Depth")?);
+            wave.set_feedback(evaluator.input_const("Feedback")?);
+            wave.set_mix(evaluator.input_const("Mix")?);
+            wave.set_lfo(evaluator.input_stream("LFO")?);
+            evaluator.output_stereo(wave, fm::Flanger::set_channel, fm::Stream::Flanger)
+        }
+        fm::Stream::Phaser(mut wave) => {
+            wave.set_stream(evaluator.input_stream("Stream")?);
+            wave.set_rate(evaluator.input_const("Rate")?);
+            wave.set_depth(evaluator.input_const("Depth")?);
+            wave.set_feedback(evaluator.input_const("Feedback")?);
+            wave.set_mix(evaluator.input_const("Mix")?);
+            wave.set_lfo(evaluator.input_stream("LFO")?);
+            evaluator.output_stereo(wave, fm::Phaser::set_channel, fm::Stream::Phaser)
+        }
+        fm::Stream::Distortion(mut wave) => {
+            wave.set_stream(evaluator.input_stream("Stream")?);
+            wave.set_drive(evaluator.input_stream("Drive")?);
+            wave.set_curve(fm::ShaperCurve::from_index(evaluator.input_choice("Curve")?));
+            wave.set_order(evaluator.input_const("Order")?);
+            wave.set_points(evaluator.input_curve("Shape")?);
+            wave.set_oversampling(1 << evaluator.input_choice("Oversampling")?);
+            evaluator.output_stream("Stream", fm::Stream::Distortion(wave))
+        }
+        fm::Stream::Bitcrusher(mut wave) => {
+            wave.set_stream(evaluator.input_stream("Stream")?);
+            wave.set_bits(evaluator.input_const("Bits")?);
+            wave.set_rate(evaluator.input_stream("Rate")?);
+            wave.set_dither(evaluator.input_choice("Dither")? == 1);
+            evaluator.output_stream("Stream", fm::Stream::Bitcrusher(wave))
+        }
+        fm::Stream::Compressor(mut wave) => {
+            wave.set_stream(evaluator.input_stream("Stream")?);
+            wave.set_threshold(evaluator.input_const("Threshold")?);
+            wave.set_ratio(evaluator.input_const("Ratio")?);
+            wave.set_attack(evaluator.input_const("Attack")?);
+            wave.set_release(evaluator.input_const("Release")?);
+            wave.set_knee(evaluator.input_const("Knee")?);
+            wave.set_makeup(evaluator.input_const("Makeup")?);
+            wave.set_sidechain(evaluator.input_stream("Sidechain")?);
+            evaluator.output_stream("Stream", fm::Stream::Compressor(wave))
+        }
+        fm::Stream::Limiter(mut wave) => {
+            wave.set_stream(evaluator.input_stream("Stream")?);
+            wave.set_threshold(evaluator.input_const("Threshold")?);
+            wave.set_lookahead(evaluator.input_const("Lookahead")?);
+            wave.set_release(evaluator.input_const("Release")?);
+            wave.set_makeup(evaluator.input_const("Makeup")?);
+            wave.set_sidechain(evaluator.input_stream("Sidechain")?);
+            evaluator.output_stream("Stream", fm::Stream::Limiter(wave))
+        }
+        fm::Stream::Pan(mut wave) => {
+            wave.set_stream(evaluator.input_stream("Stream")?);
+            wave.set_pan(evaluator.input_stream("Pan")?);
+            evaluator.output_stereo(wave, fm::Pan::set_channel, fm::Stream::Pan)
+        }
+        fm::Stream::Width(mut wave) => {
+            wave.set_left(evaluator.input_stream("Left")?);
+            wave.set_right(evaluator.input_stream("Right")?);
+            wave.set_width(evaluator.input_const("Width")?);
+            evaluator.output_stereo(wave, fm::Width::set_channel, fm::Stream::Width)
+        }
+        fm::Stream::Output(mut wave) => {
+            wave.set_name(evaluator.input_text("Name")?);
+            wave.set_left(evaluator.input_stream("Left")?);
+            wave.set_right(evaluator.input_stream("Right")?);
+            // Output nodes have nothing to connect further, the player and
+            // the exporter read the stream directly.
+            Ok(MyValueType::Stream { value: fm::Stream::Output(wave) })
+        }
+        fm::Stream::ModulatedSineWave(mut wave) => {
+            wave.set_frequency(evaluator.input_const("Frequency")?);
+            wave.set_modulator(evaluator.input_stream("Modulation")?);
+
+            evaluator.output_stream("Stream", fm::Stream::ModulatedSineWave(wave))
+        }
+        fm::Stream::Mix(mut wave) => {
+            wave.set_stream_a(evaluator.input_stream("A")?);
+            wave.set_stream_b(evaluator.input_stream("B")?);
+            wave.set_p(evaluator.input_const("p")?);
+
+            evaluator.output_stream("Stream", fm::Stream::Mix(wave))
+        }
+        fm::Stream::Const(mut wave) => {
+            wave.set_val(evaluator.input_const("Value")?);
+
+            evaluator.output_stream("Stream", fm::Stream::Const(wave))
+        }
+        fm::Stream::Empty(wave) => {
+            evaluator.output_stream("Stream", fm::Stream::Empty(wave))
+        }
+        fm::Stream::Envelope(mut wave) => {
+            wave.set_stream(evaluator.input_stream("Stream")?);
+            wave.set_ad(evaluator.input_const("Attack Duration")?);
+            wave.set_a(evaluator.input_const("Attack Amplitude")?);
+            wave.set_dd(evaluator.input_const("Decay Duration")?);
+            wave.set_sd(evaluator.input_const("Sustain Duration")?);
+            wave.set_s(evaluator.input_const("Sustain Amplitude")?);
+            wave.set_rd(evaluator.input_const("Release Duration")?);
+
+            evaluator.output_stream("Stream", fm::Stream::Envelope(wave))
+        }
+        fm::Stream::WhiteNoise(wave) => {
+            evaluator.output_stream("Stream", fm::Stream::WhiteNoise(wave))
+        }
+        fm::Stream::Parameter(mut wave) => {
+            let name = evaluator.input_text("Name")?;
+            let default = evaluator.input_const("Value")?;
+            wave.set_controls(&mut evaluator.outputs_cache.controls, &name, default);
+            evaluator.output_stream("Stream", fm::Stream::Parameter(wave))
+        }
+        fm::Stream::Note(mut wave) => {
+            wave.set_controls(&evaluator.outputs_cache.controls);
+            let outputs = [("Gate", fm::NoteOutput::Gate), ("Pitch", fm::NoteOutput::Pitch), ("Velocity", fm::NoteOutput::Velocity)];
+            let outputs = outputs.into_iter().map(|(name, output)| {
+                let mut tap = wave.clone();
+                tap.set_output(output);
+                (name, fm::Stream::Note(tap))
+            }).collect();
+            evaluator.output_streams(outputs)
+        }
+        fm::Stream::Perlin(mut wave) => {
+            wave.set_scale(evaluator.input_const("Scale")?);
+            evaluator.output_stream("Stream", fm::Stream::Perlin(wave))
+        }
+        fm::Stream::Custom(mut wave) => {
+            let (mut streams, mut params) = (0, 0);
+            for port in wave.node().ports() {
+                match port {
+                    Port::Stream(name) => {
+                        wave.set_input(streams, evaluator.input_stream(name)?);
+                        streams += 1;
+                    }
+                    Port::Param { name, .. } => {
+                        wave.set_param(params, evaluator.input_const(name)?);
+                        params += 1;
+                    }
+                }
+            }
+            let outputs = wave.node().outputs();
+            if outputs.is_empty() || outputs.len() > fm::MAX_OUTPUTS {
+                anyhow::bail!("{} needs between 1 and {} outputs", wave.node().name(), fm::MAX_OUTPUTS);
+            }
+            evaluator.output_step(outputs, fm::Stream::Custom(wave))
+        }
+        fm::Stream::Bus(_) => anyhow::bail!("Buses can't be placed in the graph"),
+    }
+}
+
+fn populate_output(
+    graph: &MyGraph,
+    outputs_cache: &mut OutputsCache,
+    node_id: NodeId,
+    param_name: &str,
+    value: MyValueType,
+) -> anyhow::Result<MyValueType> {
+    let output_id = graph[node_id].get_output(param_name)?;
+    let value = match value {
+        MyValueType::Stream { value } => MyValueType::Stream { value: outputs_cache.plan.add_step(value) },
+        value => value,
+    };
+    outputs_cache.values.insert(output_id, value.clone());
+    Ok(value)
+}
+
+// Evaluates the input value of
+fn evaluate_input(
+    graph: &MyGraph,
+    node_id: NodeId,
+    param_name: &str,
+    outputs_cache: &mut OutputsCache,
+) -> anyhow::Result<MyValueType> {
+    let input_id = graph[node_id].get_input(param_name)?;
+
+    // The output of another node is connected.
+    if let Some(other_output_id) = graph.connection(input_id) {
+        // The value was already computed due to the evaluation of some other
+        // node. We simply return value from the cache.
+        if let Some(other_value) = outputs_cache.values.get(&other_output_id) {
+            Ok(other_value.clone())
+        }
+        // This is the first time encountering this node, so we need to
+        // recursively evaluate it.
+        else {
+            // Calling this will populate the cache
+            evaluate_node(graph, graph[other_output_id].node, outputs_cache)?;
+
+            // Now that we know the value is cached, return it
+            Ok(outputs_cache
+                .values
+                .get(&other_output_id)
+                .expect("Cache should be populated")
+                .clone()
+            )
+        }
+    }
+    // No existing connection, take the inline value instead.
+    else {
+        let mut value = graph[input_id].value.clone();
+        if let MyValueType::Stream { value: stream } = &mut value {
+            stream.set_sample_rate(outputs_cache.plan.sample_rate());
+        }
+        Ok(value)
+    }
+}
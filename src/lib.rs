@@ -2,21 +2,35 @@
 #![cfg_attr(not(debug_assertions), deny(warnings))] // Forbid warnings in release builds
 #![warn(clippy::all, rust_2018_idioms)]
 
+// The editor and playback through the sound card need the `gui` feature,
+// the rest of the crate builds, renders and runs patches without it. Patches
+// are still egui_node_graph2 graphs, so egui is built either way.
+#[cfg(feature = "gui")]
 mod app;
+mod graph;
 mod fm;
+mod node;
+pub mod nodes;
+mod patch;
+mod runtime;
+#[cfg(feature = "gui")]
 pub use app::NodeGraphExample;
+pub use fm::{Controls, MAX_OUTPUTS};
+pub use node::{Port, Registry, SynthNode, SynthNodeClone};
+pub use patch::{Node, Patch, Pin};
+pub use runtime::{Program, Remote, Runtime};
 
 // ----------------------------------------------------------------------------
 // When compiling for web:
 
-#[cfg(target_arch = "wasm32")]
+#[cfg(all(target_arch = "wasm32", feature = "gui"))]
 use eframe::wasm_bindgen::{self, prelude::*};
 
 /// This is the entry-point for all the web-assembly.
 /// This is called once from the HTML.
 /// It loads the app, installs some callbacks, then returns.
 /// You can add more callbacks like this if you want to call in to your code.
-#[cfg(target_arch = "wasm32")]
+#[cfg(all(target_arch = "wasm32", feature = "gui"))]
 #[wasm_bindgen]
 pub fn start(canvas_id: &str) -> Result<(), eframe::wasm_bindgen::JsValue> {
     let app = NodeGraphExample::default();
@@ -0,0 +1,116 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::fm;
+
+/// A node type declared in one place: its ports, where the node finder
+/// lists it, and how it processes a block. Nodes registered in a
+/// [`Registry`] show up in the editor and compile into plans like the
+/// built-in ones, so new nodes don't need changes to this crate.
+pub trait SynthNode: SynthNodeClone + Send + 'static {
+    /// The name shown in the node finder and on the node.
+    fn name(&self) -> &'static str;
+
+    /// The node finder category the node is listed under.
+    fn category(&self) -> &'static str {
+        "Custom"
+    }
+
+    /// The inputs of the node, in the order `process` receives them.
+    fn ports(&self) -> Vec<Port>;
+
+    /// The names of the node's output streams, at least one and at most
+    /// [`MAX_OUTPUTS`](crate::MAX_OUTPUTS).
+    fn outputs(&self) -> Vec<&'static str> {
+        vec!["Stream"]
+    }
+
+    /// Processes one block. `inputs` holds a block for each stream port and
+    /// `params` a value for each param port, both in the order of `ports`.
+    /// One call fills the block of every output in `outputs`, which start
+    /// out silent. Every block in `inputs` and `outputs` has the same length,
+    /// fewer than usual samples means the inputs are ending.
+    fn process(&mut self, inputs: &[Vec<f32>], params: &[f32], outputs: &mut [&mut [f32]], sample_rate: u32);
+}
+
+/// Lets boxed nodes be cloned, implemented for every `SynthNode` that is
+/// `Clone`.
+pub trait SynthNodeClone {
+    fn box_clone(&self) -> Box<dyn SynthNode>;
+}
+
+impl<T: SynthNode + Clone> SynthNodeClone for T {
+    fn box_clone(&self) -> Box<dyn SynthNode> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn SynthNode> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+impl fmt::Debug for dyn SynthNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// An input of a [`SynthNode`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Port {
+    /// A signal wired from another node, silent while nothing is connected.
+    Stream(&'static str),
+    /// A value set on the node, with its default and the range it is edited in.
+    Param { name: &'static str, default: f32, range: RangeInclusive<f32> },
+}
+
+impl Port {
+    pub fn stream(name: &'static str) -> Self {
+        Self::Stream(name)
+    }
+
+    pub fn param(name: &'static str, default: f32, range: RangeInclusive<f32>) -> Self {
+        Self::Param { name, default, range }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Stream(name) => name,
+            Self::Param { name, .. } => name,
+        }
+    }
+}
+
+/// The node types the editor offers on top of the built-in ones.
+#[derive(Clone, Debug)]
+pub struct Registry {
+    nodes: Vec<Box<dyn SynthNode>>,
+}
+
+impl Registry {
+    /// A registry without any nodes.
+    pub fn empty() -> Self {
+        Self { nodes: Vec::new() }
+    }
+
+    /// Adds a node type, new nodes start as a copy of `node`.
+    pub fn register(&mut self, node: impl SynthNode) {
+        self.nodes.push(Box::new(node));
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &dyn SynthNode> {
+        self.nodes.iter().map(|node| node.as_ref())
+    }
+}
+
+impl Default for Registry {
+    // The nodes this crate writes against the trait itself.
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register(fm::Add);
+        registry.register(fm::Multiply);
+        registry
+    }
+}
@@ -0,0 +1,322 @@
+//! The built-in node types as structs, for adding nodes to a [`Patch`] with
+//! their settings checked by the compiler:
+//!
+//! ```no_run
+//! use synthi::Patch;
+//! use synthi::nodes::{Modulator, SineWave};
+//!
+//! let mut patch = Patch::new();
+//! let carrier = patch.add(Modulator { frequency: 220.0 })?;
+//! let modulator = patch.add(SineWave { frequency: 3.0, ..Default::default() })?;
+//! patch.connect(modulator, carrier.input("Modulation"))?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! Every field sets the input of the same name, and starts at the default
+//! the editor gives it. Choices are the index of the option, in the order
+//! the editor lists them. Inputs that only take connections, files, curves
+//! and partials are left out, see [`Patch::connect`] and [`Patch::load`].
+
+use crate::patch::{Node, Patch};
+
+/// A node type together with the settings its node starts with.
+pub trait NodeKind {
+    /// The name the node finder lists the node under.
+    fn name(&self) -> &'static str;
+
+    /// Sets the inputs of `node`, just added to `patch`.
+    fn apply(self, patch: &mut Patch, node: Node) -> anyhow::Result<()>;
+}
+
+// How each type of field is set on its input.
+trait Setting {
+    fn apply(self, patch: &mut Patch, node: Node, input: &str) -> anyhow::Result<()>;
+}
+
+impl Setting for f32 {
+    fn apply(self, patch: &mut Patch, node: Node, input: &str) -> anyhow::Result<()> {
+        patch.set(node.input(input), self)
+    }
+}
+
+impl Setting for usize {
+    fn apply(self, patch: &mut Patch, node: Node, input: &str) -> anyhow::Result<()> {
+        patch.set(node.input(input), self as f32)
+    }
+}
+
+impl Setting for String {
+    fn apply(self, patch: &mut Patch, node: Node, input: &str) -> anyhow::Result<()> {
+        patch.set_text(node.input(input), &self)
+    }
+}
+
+macro_rules! nodes {
+    ($(
+        $(#[$meta:meta])*
+        $kind:ident = $name:literal {
+            $( $(#[$field_meta:meta])* $field:ident: $ty:ty = $input:literal => $default:expr ),* $(,)?
+        }
+    )*) => {
+        $(
+            $(#[$meta])*
+            #[derive(Debug, Clone, PartialEq)]
+            pub struct $kind {
+                $( $(#[$field_meta])* pub $field: $ty, )*
+            }
+
+            impl Default for $kind {
+                fn default() -> Self {
+                    Self { $( $field: $default, )* }
+                }
+            }
+
+            impl NodeKind for $kind {
+                fn name(&self) -> &'static str {
+                    $name
+                }
+
+                #[allow(unused_variables)]
+                fn apply(self, patch: &mut Patch, node: Node) -> anyhow::Result<()> {
+                    $( Setting::apply(self.$field, patch, node, $input)?; )*
+                    Ok(())
+                }
+            }
+        )*
+
+        // Every kind at its defaults, to check them against the editor's.
+        #[cfg(test)]
+        pub(crate) fn all_defaults() -> Vec<(&'static str, Box<dyn FnOnce(&mut Patch) -> anyhow::Result<Node>>)> {
+            vec![$( ($name, Box::new(|patch: &mut Patch| patch.add($kind::default()))), )*]
+        }
+    };
+}
+
+nodes! {
+    /// A sine oscillator.
+    SineWave = "Sine Wave" {
+        frequency: f32 = "Frequency" => 440.0,
+        phase_shift: f32 = "Phase Shift" => 0.0,
+    }
+    /// A square oscillator, not band-limited, so high notes alias.
+    SquareWave = "Square Wave" {
+        frequency: f32 = "Frequency" => 440.0,
+        phase_shift: f32 = "Phase Shift" => 0.0,
+    }
+    /// A triangle oscillator, not band-limited, so high notes alias.
+    TriangleWave = "Triangle Wave" {
+        frequency: f32 = "Frequency" => 440.0,
+        phase_shift: f32 = "Phase Shift" => 0.0,
+    }
+    /// A sawtooth oscillator, not band-limited, so high notes alias.
+    SawtoothWave = "Sawtooth" {
+        frequency: f32 = "Frequency" => 440.0,
+        phase_shift: f32 = "Phase Shift" => 0.0,
+    }
+    /// Morphs between the frames of a wavetable, see [`Patch::load`].
+    Wavetable = "Wavetable" {
+        frequency: f32 = "Frequency" => 440.0,
+        position: f32 = "Position" => 0.0,
+    }
+    /// Plays a wav file, see [`Patch::load`]. It plays once right away, or
+    /// from each rising edge of its Gate once one is connected.
+    Sampler = "Sampler" {
+        rate: f32 = "Rate" => 1.0,
+        pitch: f32 = "Pitch" => 0.0,
+        start: f32 = "Start" => 0.0,
+        end: f32 = "End" => 1.0,
+        /// Off, Forward or Ping-Pong.
+        loop_mode: usize = "Loop Mode" => 0,
+    }
+    /// A Karplus-Strong plucked string.
+    Pluck = "Plucked String" {
+        frequency: f32 = "Frequency" => 220.0,
+        damping: f32 = "Damping" => 0.3,
+        brightness: f32 = "Brightness" => 0.7,
+    }
+    /// A sum of sine partials.
+    Additive = "Additive" {
+        frequency: f32 = "Frequency" => 220.0,
+        /// Custom, Saw, Square or Organ.
+        preset: usize = "Preset" => 0,
+        harmonics: f32 = "Harmonics" => 16.0,
+        tilt: f32 = "Tilt" => 0.0,
+    }
+    /// Detuned sawtooth voices spread across both sides.
+    Supersaw = "Supersaw" {
+        frequency: f32 = "Frequency" => 220.0,
+        voices: f32 = "Voices" => 7.0,
+        detune: f32 = "Detune" => 25.0,
+        spread: f32 = "Spread" => 0.5,
+    }
+    /// Grains of its input or of a sample.
+    Granular = "Granular" {
+        /// Input or Sample.
+        source: usize = "Source" => 0,
+        grain_size: f32 = "Grain Size" => 80.0,
+        density: f32 = "Density" => 20.0,
+        position: f32 = "Position" => 0.2,
+        position_jitter: f32 = "Position Jitter" => 0.05,
+        pitch: f32 = "Pitch" => 0.0,
+        pitch_jitter: f32 = "Pitch Jitter" => 0.0,
+    }
+    /// A biquad filter.
+    Biquad = "Biquad Filter" {
+        /// Low Pass, High Pass, Band Pass, Notch, Peaking, Low Shelf or High Shelf.
+        mode: usize = "Mode" => 0,
+        cutoff: f32 = "Cutoff" => 1000.0,
+        q: f32 = "Q" => 0.707,
+        gain: f32 = "Gain" => 0.0,
+    }
+    /// A state-variable filter with an output for each response.
+    StateVariableFilter = "State Variable Filter" {
+        cutoff: f32 = "Cutoff" => 1000.0,
+        q: f32 = "Q" => 0.707,
+    }
+    /// A four-pole ladder low pass.
+    LadderFilter = "Ladder Filter" {
+        cutoff: f32 = "Cutoff" => 1000.0,
+        resonance: f32 = "Resonance" => 0.3,
+        drive: f32 = "Drive" => 1.0,
+    }
+    /// A feedback delay.
+    Delay = "Delay" {
+        time: f32 = "Time" => 300.0,
+        feedback: f32 = "Feedback" => 0.4,
+        mix: f32 = "Mix" => 0.35,
+        feedback_cutoff: f32 = "Feedback Cutoff" => 4000.0,
+        /// Off, 1/1, 1/2, 1/4, 1/8, 1/16, 1/4 dotted, 1/8 dotted, 1/4 triplet or 1/8 triplet.
+        sync: usize = "Sync" => 0,
+        bpm: f32 = "BPM" => 120.0,
+        /// Off or On.
+        ping_pong: usize = "Ping-Pong" => 0,
+    }
+    /// A Freeverb-style stereo reverb.
+    Reverb = "Reverb" {
+        room_size: f32 = "Room Size" => 0.7,
+        damping: f32 = "Damping" => 0.5,
+        pre_delay: f32 = "Pre-Delay" => 20.0,
+        mix: f32 = "Mix" => 0.3,
+    }
+    Chorus = "Chorus" {
+        rate: f32 = "Rate" => 0.8,
+        depth: f32 = "Depth" => 0.5,
+        feedback: f32 = "Feedback" => 0.0,
+        mix: f32 = "Mix" => 0.5,
+    }
+    Flanger = "Flanger" {
+        rate: f32 = "Rate" => 0.25,
+        depth: f32 = "Depth" => 0.5,
+        feedback: f32 = "Feedback" => 0.6,
+        mix: f32 = "Mix" => 0.5,
+    }
+    Phaser = "Phaser" {
+        rate: f32 = "Rate" => 0.5,
+        depth: f32 = "Depth" => 0.7,
+        feedback: f32 = "Feedback" => 0.4,
+        mix: f32 = "Mix" => 0.5,
+    }
+    /// A waveshaper with selectable curves.
+    Distortion = "Distortion" {
+        drive: f32 = "Drive" => 2.0,
+        /// Tanh, Hard Clip, Foldback, Chebyshev or Drawn.
+        curve: usize = "Curve" => 0,
+        order: f32 = "Order" => 3.0,
+        /// 1x, 2x or 4x.
+        oversampling: usize = "Oversampling" => 0,
+    }
+    Bitcrusher = "Bitcrusher" {
+        bits: f32 = "Bits" => 8.0,
+        rate: f32 = "Rate" => 11025.0,
+        /// Off or TPDF.
+        dither: usize = "Dither" => 0,
+    }
+    Compressor = "Compressor" {
+        threshold: f32 = "Threshold" => -18.0,
+        ratio: f32 = "Ratio" => 4.0,
+        attack: f32 = "Attack" => 10.0,
+        release: f32 = "Release" => 100.0,
+        knee: f32 = "Knee" => 6.0,
+        makeup: f32 = "Makeup" => 0.0,
+    }
+    Limiter = "Limiter" {
+        threshold: f32 = "Threshold" => -1.0,
+        lookahead: f32 = "Lookahead" => 5.0,
+        release: f32 = "Release" => 100.0,
+        makeup: f32 = "Makeup" => 0.0,
+    }
+    Pan = "Pan" {
+        pan: f32 = "Pan" => 0.0,
+    }
+    Width = "Stereo Width" {
+        width: f32 = "Width" => 1.0,
+    }
+    /// A named output, the patch plays the mix of its outputs.
+    Output = "Output" {
+        name: String = "Name" => String::from("output"),
+    }
+    /// A sine wave whose frequency is modulated by its input.
+    Modulator = "Modulator" {
+        frequency: f32 = "Frequency" => 0.0,
+    }
+    Mix = "Mix" {
+        p: f32 = "p" => 0.5,
+    }
+    Const = "Const" {
+        value: f32 = "Value" => 0.5,
+    }
+    Empty = "Empty" {}
+    Envelope = "Envelope" {
+        attack_duration: f32 = "Attack Duration" => 0.3,
+        attack_amplitude: f32 = "Attack Amplitude" => 1.0,
+        decay_duration: f32 = "Decay Duration" => 0.3,
+        sustain_duration: f32 = "Sustain Duration" => 2.0,
+        sustain_amplitude: f32 = "Sustain Amplitude" => 0.6,
+        release_duration: f32 = "Release Duration" => 1.0,
+    }
+    Perlin = "Perlin Noise" {
+        scale: f32 = "Scale" => 1.0,
+    }
+    WhiteNoise = "White Noise" {}
+    /// A value set by name through a runtime's controls.
+    Parameter = "Parameter" {
+        name: String = "Name" => String::from("param"),
+        value: f32 = "Value" => 0.0,
+    }
+    /// The last note played through a runtime's controls.
+    Note = "Note" {}
+    Add = "Add" {}
+    Multiply = "Multiply" {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::{AllMyNodeTemplates, MyGraphState};
+    use crate::node::Registry;
+    use egui_node_graph2::{NodeTemplateIter, NodeTemplateTrait};
+
+    #[test]
+    fn every_node_finder_entry_has_a_kind() {
+        let registry = Registry::default();
+        let mut state = MyGraphState::default();
+        let mut names: Vec<_> = AllMyNodeTemplates { registry: &registry }.all_kinds().iter()
+            .map(|template| template.node_finder_label(&mut state).into_owned())
+            .collect();
+        let mut kinds: Vec<_> = all_defaults().into_iter().map(|(name, _)| name.to_string()).collect();
+        names.sort();
+        kinds.sort();
+        assert_eq!(names, kinds);
+    }
+
+    #[test]
+    fn defaults_match_the_editor() {
+        for (name, add) in all_defaults() {
+            let mut typed = Patch::new();
+            add(&mut typed).unwrap();
+            let mut named = Patch::new();
+            named.add_named(name).unwrap();
+            assert_eq!(typed.text().unwrap(), named.text().unwrap(), "{}", name);
+        }
+    }
+}
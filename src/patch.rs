@@ -0,0 +1,394 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use egui_node_graph2::*;
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+
+use crate::fm;
+use crate::graph::*;
+use crate::node::{Registry, SynthNode};
+use crate::nodes::NodeKind;
+
+/// A node graph built in code instead of in the editor. It holds the same
+/// nodes and compiles the same way, so it plays exactly what the editor
+/// would play for the same graph.
+///
+/// ```no_run
+/// use synthi::Patch;
+/// use synthi::nodes::{Modulator, SineWave};
+///
+/// let mut patch = Patch::new();
+/// let carrier = patch.add(Modulator { frequency: 220.0 })?;
+/// let modulator = patch.add(SineWave { frequency: 3.0, ..Default::default() })?;
+/// patch.connect(modulator, carrier.input("Modulation"))?;
+/// let samples = patch.render(carrier, 44100, 44100)?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct Patch {
+    graph: MyGraph,
+    state: MyGraphState,
+    registry: Registry,
+    // Where the editor placed the nodes of an opened file, kept for saving.
+    positions: HashMap<NodeId, [f32; 2]>,
+}
+
+/// A node of a [`Patch`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Node(NodeId);
+
+/// An input or output of a node, by the name the editor shows for it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pin {
+    node: NodeId,
+    // Only outputs may leave the name out, for the node's first output.
+    name: Option<String>,
+}
+
+impl Node {
+    pub fn input(self, name: &str) -> Pin {
+        Pin { node: self.0, name: Some(name.to_string()) }
+    }
+
+    pub fn output(self, name: &str) -> Pin {
+        Pin { node: self.0, name: Some(name.to_string()) }
+    }
+}
+
+/// A node used as the source of a connection stands for its first output.
+impl From<Node> for Pin {
+    fn from(node: Node) -> Self {
+        Pin { node: node.0, name: None }
+    }
+}
+
+impl Default for Patch {
+    fn default() -> Self {
+        Self::with_registry(Registry::default())
+    }
+}
+
+impl Patch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A patch that can also add the nodes of `registry` by name.
+    pub fn with_registry(registry: Registry) -> Self {
+        Self { graph: MyGraph::new(), state: MyGraphState::default(), registry, positions: HashMap::new() }
+    }
+
+    /// Opens a patch saved by the editor or by [`Patch::save`].
+    pub fn open(path: &str) -> anyhow::Result<Self> {
+        Self::open_with_registry(path, Registry::default())
+    }
+
+    /// Opens a patch using nodes of `registry`, which must have every node
+    /// type the patch was saved with.
+    pub fn open_with_registry(path: &str, registry: Registry) -> anyhow::Result<Self> {
+        let (graph, positions) = read_patch(&std::fs::read_to_string(path)?, &registry)?;
+        let positions = positions.into_iter()
+            .filter_map(|(node, position)| Some((node, position?)))
+            .collect();
+        Ok(Self { graph, state: MyGraphState::default(), registry, positions })
+    }
+
+    /// Saves the patch in the format the editor opens.
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        std::fs::write(path, self.text()?)?;
+        Ok(())
+    }
+
+    // The patch as it is saved.
+    pub(crate) fn text(&self) -> anyhow::Result<String> {
+        write_patch(&self.graph, |node| self.positions.get(&node).copied())
+    }
+
+    /// Adds a node of one of the types in [`crate::nodes`], set up with its
+    /// fields.
+    pub fn add(&mut self, kind: impl NodeKind) -> anyhow::Result<Node> {
+        let node = self.add_named(kind.name())?;
+        kind.apply(self, node)?;
+        Ok(node)
+    }
+
+    /// Adds a node by the name the node finder lists it under, like
+    /// `"Sine Wave"` or a registered node's name. Its inputs start at the
+    /// editor's defaults.
+    pub fn add_named(&mut self, kind: &str) -> anyhow::Result<Node> {
+        let template = template_named(&self.registry, &mut self.state, kind)?;
+        Ok(Node(add_template(&mut self.graph, &mut self.state, template)))
+    }
+
+    /// Adds a node written against the [`SynthNode`] trait, registered or not.
+    pub fn add_node(&mut self, node: impl SynthNode) -> Node {
+        let template = fm::Stream::Custom(fm::Custom::new(Box::new(node)));
+        Node(add_template(&mut self.graph, &mut self.state, template))
+    }
+
+    /// Connects an output to an input, replacing whatever the input was
+    /// connected to.
+    pub fn connect(&mut self, from: impl Into<Pin>, to: Pin) -> anyhow::Result<()> {
+        let from = from.into();
+        let output = match &from.name {
+            Some(name) => self.graph[from.node].get_output(name)?,
+            None => self.graph[from.node].output_ids().next()
+                .ok_or_else(|| anyhow::anyhow!("{} has no outputs", self.graph[from.node].label))?,
+        };
+        let input = self.input_id(&to)?;
+        if self.graph[output].typ != self.graph[input].typ {
+            anyhow::bail!("Can't connect {:?} to {:?}", self.graph[output].typ, self.graph[input].typ);
+        }
+        if let Some(previous) = self.graph.connection(input) {
+            self.graph.remove_connection(input, previous);
+        }
+        self.graph.add_connection(output, input, 0);
+        Ok(())
+    }
+
+    /// Sets the value of an unconnected input. Numbers set constants and
+    /// the fallback of modulatable inputs, and pick options of choices by
+    /// their index.
+    pub fn set(&mut self, input: Pin, value: f32) -> anyhow::Result<()> {
+        let input = self.input_id(&input)?;
+        set_number(&mut self.graph[input].value, value)
+    }
+
+    /// Sets a text input, like the name of an output node.
+    pub fn set_text(&mut self, input: Pin, value: &str) -> anyhow::Result<()> {
+        let input = self.input_id(&input)?;
+        set_text(&mut self.graph[input].value, value)
+    }
+
+    /// Loads a wav file into a wavetable or sample input. An empty path goes
+    /// back to the built-in shapes, or to no sample.
+    pub fn load(&mut self, input: Pin, path: &str) -> anyhow::Result<()> {
+        let input = self.input_id(&input)?;
+        load_file(&mut self.graph[input].value, path)
+    }
+
+    fn input_id(&self, pin: &Pin) -> anyhow::Result<InputId> {
+        let name = pin.name.as_deref().ok_or_else(|| anyhow::anyhow!("Inputs must be named"))?;
+        Ok(self.graph[pin.node].get_input(name)?)
+    }
+
+    /// Plays a node through the master stage as interleaved stereo, in
+    /// stereo when it has a Left and a Right output.
+    pub fn source(&self, node: Node, sample_rate: u32) -> anyhow::Result<impl Source<Item = f32> + Send> {
+        Ok(fm::Master::new(plan_for(&self.graph, node.0, sample_rate)?.plan))
+    }
+
+    /// Plays the mix of every output node.
+    pub fn outputs_source(&self, sample_rate: u32) -> anyhow::Result<impl Source<Item = f32> + Send> {
+        let mut compiled = OutputsCache::new(sample_rate);
+        for node in audible_outputs(&self.graph, &self.state) {
+            add_output_node(&self.graph, node, &mut compiled)?;
+        }
+        Ok(fm::Master::new(compiled.plan))
+    }
+
+    /// Renders `frames` frames of a node as interleaved stereo samples, fewer
+    /// if the node ends first.
+    pub fn render(&self, node: Node, sample_rate: u32, frames: usize) -> anyhow::Result<Vec<f32>> {
+        Ok(self.source(node, sample_rate)?.take(frames * 2).collect())
+    }
+
+    /// Writes `seconds` of a node to a 16-bit stereo wav file.
+    pub fn export(&self, node: Node, path: &str, sample_rate: u32, seconds: f32) -> anyhow::Result<()> {
+        fm::write_wavs(vec![(path.to_string(), self.source(node, sample_rate)?)], seconds)
+    }
+
+    // Compiles the mix of every audible output node, or `node` alone.
+    pub(crate) fn compile(&self, node: Option<Node>, sample_rate: u32) -> anyhow::Result<OutputsCache> {
+        if let Some(node) = node {
+            return plan_for(&self.graph, node.0, sample_rate);
+        }
+        let outputs = audible_outputs(&self.graph, &self.state);
+        if outputs.is_empty() {
+            anyhow::bail!("The patch has no output nodes");
+        }
+        let mut compiled = OutputsCache::new(sample_rate);
+        for node in outputs {
+            add_output_node(&self.graph, node, &mut compiled)?;
+        }
+        Ok(compiled)
+    }
+
+    /// The nodes that nothing `node` plays depends on.
+    pub fn unreachable(&self, node: Node) -> anyhow::Result<Vec<Node>> {
+        let compiled = plan_for(&self.graph, node.0, 44100)?;
+        Ok(compiled.unreachable(&self.graph).into_iter().map(Node).collect())
+    }
+}
+
+fn set_number(value: &mut MyValueType, number: f32) -> anyhow::Result<()> {
+    match value {
+        MyValueType::Const { value: constant } => *constant = number,
+        MyValueType::Stream { value: fm::Stream::Const(constant) } => constant.set_val(number),
+        MyValueType::Choice { value: choice, options } => {
+            if number < 0.0 || number as usize >= options.len() {
+                anyhow::bail!("Option {} is out of range, there are {}", number, options.len());
+            }
+            *choice = number as usize;
+        }
+        other => anyhow::bail!("Can't set {:?} to a number", other),
+    }
+    Ok(())
+}
+
+fn set_text(value: &mut MyValueType, text: &str) -> anyhow::Result<()> {
+    match value {
+        MyValueType::Text { value } => *value = text.to_string(),
+        other => anyhow::bail!("Can't set {:?} to text", other),
+    }
+    Ok(())
+}
+
+fn load_file(value: &mut MyValueType, path: &str) -> anyhow::Result<()> {
+    match value {
+        MyValueType::Wavetable { path: current, bank } => {
+            *bank = Arc::new(if path.is_empty() { fm::WavetableBank::shapes() } else { fm::WavetableBank::load(path)? });
+            *current = path.to_string();
+        }
+        MyValueType::Sample { path: current, sample } => {
+            *sample = Arc::new(if path.is_empty() { fm::SampleBuffer::default() } else { fm::SampleBuffer::load(path)? });
+            *current = path.to_string();
+        }
+        other => anyhow::bail!("Can't load a file into {:?}", other),
+    }
+    Ok(())
+}
+
+// A saved patch: its nodes by the name the node finder lists them under,
+// the values of their inputs and the connections between them. Nodes are
+// rebuilt from their templates when opened, so inputs a node gained since
+// the file was saved start at their defaults.
+#[derive(Serialize, Deserialize)]
+struct PatchFile {
+    nodes: Vec<NodeFile>,
+    connections: Vec<ConnectionFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct NodeFile {
+    kind: String,
+    #[serde(default)]
+    position: Option<[f32; 2]>,
+    #[serde(default)]
+    inputs: Vec<(String, ValueFile)>,
+}
+
+// Nodes are referred to by their index in `nodes`.
+#[derive(Serialize, Deserialize)]
+struct ConnectionFile {
+    from: usize,
+    output: String,
+    to: usize,
+    input: String,
+}
+
+#[derive(Serialize, Deserialize)]
+enum ValueFile {
+    Number(f32),
+    Choice(usize),
+    Text(String),
+    File(String),
+    // Ratio, amplitude and detune of each partial.
+    Partials(Vec<[f32; 3]>),
+    Curve(Vec<f32>),
+}
+
+impl ValueFile {
+    // Streams only have a value to save while they are constant.
+    fn from_value(value: &MyValueType) -> Option<Self> {
+        Some(match value {
+            MyValueType::Stream { value: fm::Stream::Const(constant) } => Self::Number(constant.val()),
+            MyValueType::Stream { .. } => return None,
+            MyValueType::Const { value } => Self::Number(*value),
+            MyValueType::Wavetable { path, .. } | MyValueType::Sample { path, .. } => Self::File(path.clone()),
+            MyValueType::Choice { value, .. } => Self::Choice(*value),
+            MyValueType::Partials { value } => Self::Partials(value.iter().map(|p| [p.ratio, p.amplitude, p.detune]).collect()),
+            MyValueType::Curve { value } => Self::Curve(value.clone()),
+            MyValueType::Text { value } => Self::Text(value.clone()),
+        })
+    }
+
+    fn apply(self, value: &mut MyValueType) -> anyhow::Result<()> {
+        match (self, value) {
+            (Self::Number(number), value) => set_number(value, number)?,
+            (Self::Choice(choice), value) => set_number(value, choice as f32)?,
+            (Self::Text(text), value) => set_text(value, &text)?,
+            (Self::File(path), value) => load_file(value, &path)?,
+            (Self::Partials(partials), MyValueType::Partials { value }) => {
+                *value = partials.into_iter().map(|[ratio, amplitude, detune]| fm::Partial { ratio, amplitude, detune }).collect();
+            }
+            (Self::Curve(curve), MyValueType::Curve { value }) => *value = curve,
+            (_, other) => anyhow::bail!("Can't set {:?} from a patch file", other),
+        }
+        Ok(())
+    }
+}
+
+// Writes a graph as a patch file, `position` gives where each node is
+// drawn if it is drawn anywhere.
+pub(crate) fn write_patch(graph: &MyGraph, position: impl Fn(NodeId) -> Option<[f32; 2]>) -> anyhow::Result<String> {
+    let mut state = MyGraphState::default();
+    let ids: Vec<NodeId> = graph.nodes.keys().collect();
+    let index = |node: NodeId| ids.iter().position(|&id| id == node).unwrap_or_default();
+    let mut file = PatchFile { nodes: vec![], connections: vec![] };
+    for &node_id in &ids {
+        let node = &graph[node_id];
+        let inputs = node.inputs.iter()
+            .filter_map(|(name, input)| Some((name.clone(), ValueFile::from_value(&graph[*input].value)?)))
+            .collect();
+        file.nodes.push(NodeFile {
+            kind: node.user_data.template.node_finder_label(&mut state).into_owned(),
+            position: position(node_id),
+            inputs,
+        });
+        for (name, input) in &node.inputs {
+            if let Some(output) = graph.connection(*input) {
+                let from = graph[output].node;
+                let output = graph[from].outputs.iter()
+                    .find(|(_, id)| *id == output)
+                    .map(|(name, _)| name.clone())
+                    .unwrap_or_default();
+                file.connections.push(ConnectionFile { from: index(from), output, to: index(node_id), input: name.clone() });
+            }
+        }
+    }
+    Ok(ron::ser::to_string_pretty(&file, ron::ser::PrettyConfig::default())?)
+}
+
+// The nodes of an opened file, with the position each was saved at.
+pub(crate) type Positions = Vec<(NodeId, Option<[f32; 2]>)>;
+
+// Reads a patch file into a new graph.
+pub(crate) fn read_patch(text: &str, registry: &Registry) -> anyhow::Result<(MyGraph, Positions)> {
+    let file: PatchFile = ron::from_str(text)?;
+    let mut state = MyGraphState::default();
+    let mut graph = MyGraph::new();
+    let mut nodes = vec![];
+    for node in file.nodes {
+        let template = template_named(registry, &mut state, &node.kind)?;
+        let node_id = add_template(&mut graph, &mut state, template);
+        for (name, value) in node.inputs {
+            let input = graph[node_id].get_input(&name)
+                .map_err(|_| anyhow::anyhow!("{} has no input called {}", node.kind, name))?;
+            value.apply(&mut graph[input].value)
+                .map_err(|err| anyhow::anyhow!("{} of {}: {}", name, node.kind, err))?;
+        }
+        nodes.push((node_id, node.position));
+    }
+    for connection in file.connections {
+        let node = |index: usize| nodes.get(index).map(|&(node, _)| node)
+            .ok_or_else(|| anyhow::anyhow!("There is no node {} to connect", index));
+        let output = graph[node(connection.from)?].get_output(&connection.output)?;
+        let input = graph[node(connection.to)?].get_input(&connection.input)?;
+        if graph[output].typ != graph[input].typ {
+            anyhow::bail!("Can't connect {} to {}", connection.output, connection.input);
+        }
+        graph.add_connection(output, input, 0);
+    }
+    Ok((graph, nodes))
+}
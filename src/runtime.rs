@@ -0,0 +1,334 @@
+use rodio::Source;
+use rtrb::{Consumer, Producer, RingBuffer};
+
+use crate::fm::{self, Controls};
+use crate::graph::OutputsCache;
+use crate::patch::{Node, Patch};
+
+// How many samples the runtime plays between checks for commands when it
+// is played as a rodio source, a block of stereo frames.
+const COMMAND_INTERVAL: usize = 2 * fm::BLOCK_SIZE;
+
+/// A patch compiled for a host's own audio callback, in place of a rodio
+/// sink. Compiling allocates everything the patch needs up front, so
+/// [`Runtime::process`] neither allocates nor locks and can run on a
+/// realtime audio thread. Parameters and notes are changed through the
+/// [`Controls`] of the runtime, from any thread, or in order with other
+/// commands through a [`Remote`].
+///
+/// ```no_run
+/// use synthi::{Patch, Runtime};
+///
+/// let patch = Patch::open("patch.ron")?;
+/// let mut runtime = Runtime::new(&patch, 48000)?;
+/// let controls = runtime.controls();
+/// controls.note_on(60, 0.8);
+/// controls.set("cutoff", 1200.0);
+///
+/// let mut buffer = [0f32; 512];
+/// runtime.process(&mut buffer);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct Runtime {
+    program: Box<Program>,
+    finished: bool,
+    commands: Option<Consumer<Command>>,
+    retired: Option<Producer<Box<Program>>>,
+    // Samples played since commands were last received.
+    elapsed: usize,
+}
+
+/// A compiled patch, played by a [`Runtime`] or swapped into a running one.
+pub struct Program {
+    master: fm::Master,
+    controls: Controls,
+}
+
+// What a remote sends, applied in order by the runtime.
+enum Command {
+    // Parameters are looked up by name on the remote's side.
+    Param { index: usize, value: f32 },
+    NoteOn { note: u8, velocity: f32 },
+    NoteOff,
+    // The old program is sent back to be dropped.
+    Swap(Box<Program>),
+}
+
+/// The sending end of a runtime's command queue, kept on the thread that
+/// edits the patch. Commands are applied in order at the start of the
+/// runtime's next block, and programs swapped out come back here, so the
+/// audio thread never waits or frees memory.
+pub struct Remote {
+    commands: Producer<Command>,
+    retired: Consumer<Box<Program>>,
+    // The controls of the last program sent, to look parameters up by name.
+    controls: Controls,
+    sample_rate: u32,
+}
+
+impl Program {
+    /// The mix of the patch's output nodes.
+    pub fn new(patch: &Patch, sample_rate: u32) -> anyhow::Result<Self> {
+        Ok(Self::compiled(patch.compile(None, sample_rate)?))
+    }
+
+    /// A single node of the patch, in stereo when it has a Left and a Right
+    /// output.
+    pub fn for_node(patch: &Patch, node: Node, sample_rate: u32) -> anyhow::Result<Self> {
+        Ok(Self::compiled(patch.compile(Some(node), sample_rate)?))
+    }
+
+    // Plays nothing, and ends right away.
+    pub(crate) fn silence(sample_rate: u32) -> Self {
+        Self::compiled(OutputsCache::new(sample_rate))
+    }
+
+    pub(crate) fn compiled(compiled: OutputsCache) -> Self {
+        let mut master = fm::Master::new(compiled.plan);
+        master.prime();
+        Self { master, controls: compiled.controls }
+    }
+
+    pub fn controls(&self) -> Controls {
+        self.controls.clone()
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.master.sample_rate()
+    }
+}
+
+impl Runtime {
+    /// Plays the mix of the patch's output nodes.
+    pub fn new(patch: &Patch, sample_rate: u32) -> anyhow::Result<Self> {
+        Ok(Self::with_program(Program::new(patch, sample_rate)?))
+    }
+
+    /// Plays a single node of the patch, in stereo when it has a Left and a
+    /// Right output.
+    pub fn for_node(patch: &Patch, node: Node, sample_rate: u32) -> anyhow::Result<Self> {
+        Ok(Self::with_program(Program::for_node(patch, node, sample_rate)?))
+    }
+
+    pub fn with_program(program: Program) -> Self {
+        Self { program: Box::new(program), finished: false, commands: None, retired: None, elapsed: 0 }
+    }
+
+    /// Opens a command queue holding up to `capacity` commands and returns
+    /// its sending end, replacing any queue opened before. Call it before
+    /// handing the runtime to the audio thread, the queue is allocated here.
+    pub fn remote(&mut self, capacity: usize) -> Remote {
+        let (commands, receiver) = RingBuffer::new(capacity);
+        let (sender, retired) = RingBuffer::new(capacity);
+        self.commands = Some(receiver);
+        self.retired = Some(sender);
+        Remote { commands, retired, controls: self.program.controls(), sample_rate: self.sample_rate() }
+    }
+
+    /// The parameters and notes of the playing program. Clones share their
+    /// values with the runtime, so they can be sent to the threads setting them.
+    pub fn controls(&self) -> Controls {
+        self.program.controls()
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.program.sample_rate()
+    }
+
+    /// Fills `out` with interleaved stereo samples, after applying the
+    /// commands sent since the last call. Once the program has ended the
+    /// rest is silence. `out` must hold whole frames, an even number of
+    /// samples, or the sides swap from the next call on.
+    pub fn process(&mut self, out: &mut [f32]) {
+        debug_assert!(out.len() % 2 == 0, "Runtime::process needs whole stereo frames");
+        self.receive();
+        for sample in out.iter_mut() {
+            *sample = self.next_sample();
+        }
+    }
+
+    /// Whether the program has ended, programs with oscillators never do.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        let next = if self.finished { None } else { self.program.master.next() };
+        self.finished = next.is_none();
+        next.unwrap_or(0.0)
+    }
+
+    // Applies every queued command. A swap waits in the queue while the
+    // queue of retired programs is full, rather than dropping the old
+    // program here.
+    fn receive(&mut self) {
+        self.elapsed = 0;
+        let (commands, retired) = match (&mut self.commands, &mut self.retired) {
+            (Some(commands), Some(retired)) => (commands, retired),
+            _ => return,
+        };
+        loop {
+            if matches!(commands.peek(), Ok(Command::Swap(_))) && retired.is_full() {
+                return;
+            }
+            let command = match commands.pop() {
+                Ok(command) => command,
+                Err(_) => return,
+            };
+            match command {
+                Command::Param { index, value } => {
+                    self.program.controls.set_index(index, value);
+                }
+                Command::NoteOn { note, velocity } => self.program.controls.note_on(note, velocity),
+                Command::NoteOff => self.program.controls.note_off(),
+                Command::Swap(mut program) => {
+                    std::mem::swap(&mut self.program, &mut program);
+                    self.finished = false;
+                    // Can't fail, there was room before popping the swap.
+                    let _ = retired.push(program);
+                }
+            }
+        }
+    }
+}
+
+/// Plays forever, silent once the program ends, so a sink keeps the
+/// runtime around for the programs swapped in later.
+impl Iterator for Runtime {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        // Only between frames, so a swap can't trade the sides.
+        if self.elapsed >= COMMAND_INTERVAL {
+            self.receive();
+        }
+        self.elapsed += 1;
+        Some(self.next_sample())
+    }
+}
+
+impl Source for Runtime {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        2 // Interleaved stereo
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.program.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+impl Remote {
+    /// Sets a parameter of the last program sent, by name.
+    pub fn set(&mut self, name: &str, value: f32) -> anyhow::Result<()> {
+        let index = self.controls.index(name)
+            .ok_or_else(|| anyhow::anyhow!("No parameter is called {}", name))?;
+        self.send(Command::Param { index, value })
+    }
+
+    /// Plays a MIDI note, `velocity` goes from 0 to 1.
+    pub fn note_on(&mut self, note: u8, velocity: f32) -> anyhow::Result<()> {
+        self.send(Command::NoteOn { note, velocity })
+    }
+
+    pub fn note_off(&mut self) -> anyhow::Result<()> {
+        self.send(Command::NoteOff)
+    }
+
+    /// Replaces the playing program. It must run at the runtime's rate.
+    pub fn swap(&mut self, program: Program) -> anyhow::Result<()> {
+        if program.sample_rate() != self.sample_rate {
+            anyhow::bail!("Can't play a program at {} Hz on a runtime at {} Hz", program.sample_rate(), self.sample_rate);
+        }
+        let controls = program.controls();
+        self.send(Command::Swap(Box::new(program)))?;
+        self.controls = controls;
+        Ok(())
+    }
+
+    /// Stops whatever is playing.
+    pub fn stop(&mut self) -> anyhow::Result<()> {
+        self.swap(Program::silence(self.sample_rate))
+    }
+
+    /// Drops the programs the runtime has swapped out. Sending a command
+    /// does this too, but a remote that rarely sends should call it now
+    /// and then.
+    pub fn collect(&mut self) {
+        while self.retired.pop().is_ok() {}
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // Never waits, a full queue is an error the caller can retry.
+    fn send(&mut self, command: Command) -> anyhow::Result<()> {
+        self.collect();
+        self.commands.push(command).map_err(|_| anyhow::anyhow!("The command queue is full"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nodes::{Biquad, Output, SineWave};
+
+    // A sine wave played through an output node.
+    fn sine_patch() -> anyhow::Result<Patch> {
+        let mut patch = Patch::new();
+        let sine = patch.add(SineWave { frequency: 220.0, ..Default::default() })?;
+        let output = patch.add(Output::default())?;
+        patch.connect(sine, output.input("Left"))?;
+        patch.connect(sine, output.input("Right"))?;
+        Ok(patch)
+    }
+
+    fn render(runtime: &mut Runtime) -> Vec<f32> {
+        let mut out = vec![0f32; 4 * fm::BLOCK_SIZE];
+        runtime.process(&mut out);
+        out
+    }
+
+    #[test]
+    fn reopened_patch_renders_the_same() -> anyhow::Result<()> {
+        let patch = sine_patch()?;
+        let path = std::env::temp_dir().join(format!("synthi-test-{}.ron", std::process::id()));
+        let path = path.to_str().unwrap();
+        patch.save(path)?;
+        let reopened = Patch::open(path);
+        std::fs::remove_file(path)?;
+        let expected = render(&mut Runtime::new(&patch, 48000)?);
+        assert!(expected.iter().any(|&x| x != 0.0));
+        assert_eq!(render(&mut Runtime::new(&reopened?, 48000)?), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn cycles_are_errors() -> anyhow::Result<()> {
+        let mut patch = sine_patch()?;
+        let first = patch.add(Biquad::default())?;
+        let second = patch.add(Biquad::default())?;
+        patch.connect(first, second.input("Stream"))?;
+        patch.connect(second, first.input("Stream"))?;
+        assert!(Runtime::for_node(&patch, second, 48000).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn stop_silences_the_runtime() -> anyhow::Result<()> {
+        let mut runtime = Runtime::new(&sine_patch()?, 48000)?;
+        let mut remote = runtime.remote(4);
+        assert!(render(&mut runtime).iter().any(|&x| x != 0.0));
+        remote.stop()?;
+        assert!(render(&mut runtime).iter().all(|&x| x == 0.0));
+        Ok(())
+    }
+}